@@ -0,0 +1,51 @@
+//! Feeds arbitrary text through SSSParser and Script::new, which must report ParseErrors rather than panic
+//!
+//! ```text
+//! cargo +nightly fuzz run parse -- -dict=fuzz/sss.dict
+//! ```
+#![no_main]
+#![allow(dead_code)]
+
+#[macro_use]
+extern crate pest_derive;
+#[macro_use]
+extern crate log;
+
+use libfuzzer_sys::fuzz_target;
+use pest::Parser;
+
+// the modules Script::new needs, taken from the binary
+#[path = "../../src/script.rs"]
+mod script;
+#[path = "../../src/expression.rs"]
+mod expression;
+#[path = "../../src/parse_error.rs"]
+mod parse_error;
+#[path = "../../src/warning.rs"]
+mod warning;
+#[path = "../../src/diagnostics.rs"]
+mod diagnostics;
+#[path = "../../src/ir_emitter.rs"]
+mod ir_emitter;
+#[path = "../../src/source_emitter.rs"]
+mod source_emitter;
+#[path = "../../src/library.rs"]
+mod library;
+#[path = "../../src/exec_string.rs"]
+mod exec_string;
+#[path = "../../src/units.rs"]
+mod units;
+#[path = "../../src/builtins/mod.rs"]
+mod builtins;
+
+#[derive(Parser)]
+#[grammar = "../src/grammar.pest"] // relative to fuzz
+struct SSSParser;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        if let Ok(mut pairs) = SSSParser::parse(Rule::script, source) {
+            let _ = script::Script::new(pairs.next().unwrap());
+        }
+    }
+});
@@ -0,0 +1,18 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Splitting and joining lines of CSV, quoted as in RFC 4180: a field with the delimiter, a quote,
+/// or a newline in it is wrapped in quotes, and a quote inside one is doubled. Passing a tab as the
+/// delimiter reads and writes TSV.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+    let fields = || VarDef::from_array(&VarType::String);
+
+    // an unterminated quote is an error naming the line
+    let split = registry.register("csv_split", vec![param("line", str_type()), param("delimiter", str_type())], Some(fields()));
+    split.defaults = vec![String::from("\",\"")];
+
+    let join = registry.register("csv_join", vec![param("fields", fields()), param("delimiter", str_type())], Some(str_type()));
+    join.defaults = vec![String::from("\",\"")];
+}
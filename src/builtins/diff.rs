@@ -0,0 +1,16 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Comparing text. A diff is unified, with 3 lines of context, and "" when there are no changes.
+/// Diffs of strings have no timestamps in their headers, so they are the same on every run, while
+/// diffs of files have the modified time of each file, as diff -u does.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    registry.register("diff", vec![param("a", str_type()), param("b", str_type())], Some(str_type()));
+    registry.register("diff_files", vec![param("a", str_type()), param("b", str_type())], Some(str_type())); // paths
+
+    // 1 when the strings are equal, checking their lengths first
+    registry.register("same", vec![param("a", str_type()), param("b", str_type())], Some(VarDef::from_type(&VarType::Number)));
+}
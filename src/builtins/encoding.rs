@@ -0,0 +1,31 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Encoding, hashing, and UUIDs, all strings in and out
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    #[cfg(feature = "hashing")]
+    register_hashing(registry);
+
+    // UUIDs are returned in the lowercase hyphenated form
+    registry.register("uuid", vec![], Some(str_type()));
+    registry.register("uuid_v5", vec![param("namespace", str_type()), param("name", str_type())], Some(str_type()));
+}
+
+/// The checksums and encodings, left out of builds without the hashing feature.
+/// Hashes are lowercase hex, files are hashed as they are read rather than read in whole,
+/// and input that does not decode is an error naming the position of the first bad character.
+#[cfg(feature = "hashing")]
+fn register_hashing(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    for name in &["base64_encode", "base64_decode", "hex_encode", "hex_decode", "sha256", "md5"] {
+        registry.register(name, vec![param("s", str_type())], Some(str_type()));
+    }
+
+    for name in &["sha256_file", "md5_file"] {
+        registry.register(name, vec![param("path", str_type())], Some(str_type()));
+    }
+}
@@ -0,0 +1,13 @@
+use crate::expression::{VarDef, VarType};
+
+use super::BuiltinRegistry;
+
+/// Files and directories. Temp files and directories are created under the system's temp location
+/// as soon as they are asked for, with names no other script can be given, and removed when the
+/// script finishes, whether it succeeds or fails.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    registry.register("temp_file", vec![], Some(str_type()));
+    registry.register("temp_dir", vec![], Some(str_type()));
+}
@@ -0,0 +1,16 @@
+use crate::expression::{VarDef, VarType};
+
+use super::BuiltinRegistry;
+
+/// Information about the machine and user running the script, looked up without running any commands.
+/// os() is one of "linux", "macos", "windows", or "other", and arch() is the name Rust gives the CPU
+/// architecture, like "x86_64" or "aarch64".
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    for name in &["hostname", "username", "os", "arch"] {
+        registry.register(name, vec![], Some(str_type()));
+    }
+
+    registry.register("num_cpus", vec![], Some(VarDef::from_type(&VarType::Number)));
+}
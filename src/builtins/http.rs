@@ -0,0 +1,14 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Blocking HTTP GET requests, so a script can check an endpoint or fetch a file without curl.
+/// Requests time out after the script's default timeout. A status other than 2xx is an error naming
+/// the status and the start of the body, except for http_status, which returns it.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let url = || param("url", VarDef::from_type(&VarType::String));
+
+    registry.register("http_get", vec![url()], Some(VarDef::from_type(&VarType::String)));
+    registry.register("http_get_pipe", vec![url()], Some(VarDef::from_type(&VarType::Pipe))); // the body is streamed
+    registry.register("http_status", vec![url()], Some(VarDef::from_type(&VarType::Number)));
+}
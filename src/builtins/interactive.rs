@@ -0,0 +1,23 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Asking the user for input, and printing to them. The message of a prompt goes to stderr,
+/// so it never ends up in the output of the script, and the line read back has its trailing newline removed.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    registry.register("prompt", vec![param("msg", str_type())], Some(str_type()));
+
+    // the input is not echoed when reading from a terminal, for passwords
+    registry.register("prompt_secret", vec![param("msg", str_type())], Some(str_type()));
+
+    // adds " [y/N] " to the message, and is 1 only for an answer of y or yes in any case
+    registry.register("confirm", vec![param("msg", str_type())], Some(VarDef::from_type(&VarType::Number)));
+
+    // prints each argument on stdout, separated by spaces, and then a newline
+    registry.register("print_args", vec![param("args", VarDef::from_array(&VarType::String))], None).is_variadic = true;
+
+    // a prompt at the end of stdin returns "", and eof() is 1 from then on
+    registry.register("eof", vec![], Some(VarDef::from_type(&VarType::Number)));
+}
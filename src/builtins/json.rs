@@ -0,0 +1,18 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Reading and writing JSON. Maps only hold one type and arrays don't nest, so a document is
+/// parsed one level at a time: each member is a string, with strings as their text, null as "",
+/// and numbers, true, false, objects and arrays as their JSON, which can be parsed again.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    registry.register("json_parse", vec![param("json", str_type())], Some(VarDef::from_type(&VarType::Map(Box::new(VarType::String)))));
+
+    // the value at a JSON Pointer, like "/items/0/name", as one element, or no elements when nothing is there
+    registry.register("json_get", vec![param("json", str_type()), param("pointer", str_type())], Some(VarDef::from_array(&VarType::String)));
+
+    // the inverse of json_parse, values that are JSON objects, arrays or numbers are written as they are
+    registry.register("json_stringify", vec![param("m", VarDef::from_type(&VarType::Map(Box::new(VarType::String))))], Some(str_type()));
+}
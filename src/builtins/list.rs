@@ -0,0 +1,19 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Higher-order helpers over string arrays, each taking the function to apply
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+    let num_type = || VarDef::from_type(&VarType::Number);
+    let items = || param("items", VarDef::from_array(&VarType::String));
+    let fun_type = |params: Vec<VarDef>, ret: VarDef| VarDef::from_type(&VarType::Fun(params, Some(Box::new(ret))));
+
+    registry.register("map", vec![items(), param("f", fun_type(vec![str_type()], str_type()))], Some(VarDef::from_array(&VarType::String)));
+
+    // keeps the items the function returns non-zero for
+    registry.register("filter", vec![items(), param("keep", fun_type(vec![str_type()], num_type()))], Some(VarDef::from_array(&VarType::String)));
+
+    // the comparator returns a negative number, zero, or a positive number, like a - b
+    registry.register("sort_by", vec![items(), param("cmp", fun_type(vec![str_type(), str_type()], num_type()))], Some(VarDef::from_array(&VarType::String)));
+}
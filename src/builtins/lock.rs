@@ -0,0 +1,13 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Exclusive advisory locks on files, so only one run of a script does its work at a time. A lock is held
+/// until the script finishes, and taking a lock the script already holds succeeds again without waiting.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let path = || param("path", VarDef::from_type(&VarType::String));
+
+    // 1 when the lock is taken, 0 when another process holds it
+    registry.register("lock", vec![path()], Some(VarDef::from_type(&VarType::Number)));
+    registry.register("lock_wait", vec![path()], None);
+}
@@ -0,0 +1,17 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// The script's own logging, kept apart from the log of sss itself. Each line goes to stderr with a
+/// timestamp, its level, and a "script" tag, and lines below the script's log level are left out.
+/// The levels are debug, info, warn, and error, and the level starts at info.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    for name in &["log_debug", "log_info", "log_warn", "log_error"] {
+        registry.register(name, vec![param("msg", str_type())], None);
+    }
+
+    // any name other than the four levels is an error listing them
+    registry.register("set_log_level", vec![param("level", str_type())], None);
+}
@@ -0,0 +1,42 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Membership, keys, and removal for maps. The map params take maps with any type of
+/// values, map[str] only stands in for them as arguments are not checked against params.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+    let map_type = || VarDef::from_type(&VarType::Map(Box::new(VarType::String)));
+
+    // 1 when the key is in the map, otherwise 0
+    registry.register("has", vec![param("m", map_type()), param("key", str_type())], Some(VarDef::from_type(&VarType::Number)));
+
+    // the keys are returned sorted
+    registry.register("keys", vec![param("m", map_type())], Some(VarDef::from_array(&VarType::String)));
+    registry.register("remove", vec![param("m", map_type()), param("key", str_type())], None);
+
+    // all of them are also methods of maps, like m.has("key")
+    for name in &["has", "keys", "remove"] {
+        registry.register_method(&VarType::Map(Box::new(VarType::String)), name, name);
+    }
+
+    // aggregates over the values of a map of counts. An empty map has no minimum or maximum, so
+    // those return an array with at most one element, while its sum is 0. Ties go to the key that sorts first.
+    let counts = || param("m", VarDef::from_type(&VarType::Map(Box::new(VarType::Number))));
+
+    registry.register("map_sum", vec![counts()], Some(VarDef::from_type(&VarType::Number)));
+
+    for name in &["map_min_val", "map_max_val"] {
+        registry.register(name, vec![counts()], Some(VarDef::from_array(&VarType::Number)));
+    }
+
+    for name in &["map_min_key", "map_max_key"] {
+        registry.register(name, vec![counts()], Some(VarDef::from_array(&VarType::String)));
+    }
+
+    // the sort is stable, so keys with the same value stay sorted by key
+    registry.register("map_sort_by_val", vec![counts()], Some(VarDef::from_array(&VarType::String)));
+
+    // arrays don't nest, so each pair is a map with the keys "key" and "value"
+    registry.register("map_to_pairs", vec![param("m", map_type())], Some(VarDef::from_array(&VarType::Map(Box::new(VarType::String)))));
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+
+use crate::expression::{Function, Variable, VarDef, VarType};
+
+mod csv;
+mod diff;
+mod encoding;
+mod files;
+mod host;
+#[cfg(feature = "http")]
+mod http;
+mod interactive;
+mod json;
+mod list;
+mod lock;
+mod logging;
+mod map;
+mod pipe;
+mod process;
+mod stats;
+mod string;
+mod template;
+mod units;
+
+/// The methods of each type, each a built-in that is called with the receiver as its first argument.
+/// Only the methods here can be called with method syntax, a free function cannot.
+#[derive(Debug, Clone)]
+pub struct MethodTable {
+    methods: HashMap<(&'static str, String), Function> // by the canonical name of the receiver's type, and the method's name
+}
+
+impl MethodTable {
+    pub fn new() -> MethodTable {
+        MethodTable { methods: HashMap::new() }
+    }
+
+    pub fn insert(&mut self, receiver: &VarType, method: &str, fun: Function) {
+        self.methods.insert((receiver.canonical_name(), String::from(method)), fun);
+    }
+
+    /// Returns the built-in called by the method of a receiver with the given type.
+    /// Arrays have no methods, except pipe arrays, which call a pipe method on each pipe.
+    pub fn get(&self, receiver: &VarDef, method: &str) -> Option<&Function> {
+        if receiver.is_array && receiver.var_type != VarType::Pipe {
+            return None;
+        }
+
+        self.methods.get(&(receiver.var_type.canonical_name(), String::from(method)))
+    }
+
+    /// The names of the methods of the type, sorted
+    pub fn names(&self, receiver: &VarType) -> Vec<&String> {
+        let mut names = self.methods.keys().filter(|(t, _)| *t == receiver.canonical_name()).map(|(_, name)| name).collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+}
+
+/// The built-in functions a script can call, by name, and the methods of each type.
+/// Each module of built-ins adds its functions with a register function.
+#[derive(Debug, Clone)]
+pub struct BuiltinRegistry {
+    functions: HashMap<String, Function>,
+    methods: MethodTable
+}
+
+impl BuiltinRegistry {
+    /// A registry without any built-ins
+    pub fn new() -> BuiltinRegistry {
+        BuiltinRegistry { functions: HashMap::new(), methods: MethodTable::new() }
+    }
+
+    /// A registry with all of the standard built-ins
+    pub fn standard() -> BuiltinRegistry {
+        let mut registry = BuiltinRegistry::new();
+
+        pipe::register(&mut registry);
+        encoding::register(&mut registry);
+        process::register(&mut registry);
+        map::register(&mut registry);
+        list::register(&mut registry);
+        interactive::register(&mut registry);
+        string::register(&mut registry);
+        json::register(&mut registry);
+        csv::register(&mut registry);
+        files::register(&mut registry);
+        stats::register(&mut registry);
+        host::register(&mut registry);
+        lock::register(&mut registry);
+        logging::register(&mut registry);
+        units::register(&mut registry);
+        diff::register(&mut registry);
+        template::register(&mut registry);
+
+        #[cfg(feature = "http")]
+        http::register(&mut registry);
+
+        registry
+    }
+
+    /// Adds a built-in, replacing any existing one with the same name,
+    /// and returns it so the caller can mark it as not returning
+    pub fn register(&mut self, name: &str, params: Vec<Variable>, ret_type: Option<VarDef>) -> &mut Function {
+        self.functions.insert(String::from(name), Function::new(name, params, ret_type));
+        self.functions.get_mut(name).unwrap()
+    }
+
+    /// Makes the built-in fun_name callable as a method of receiver, which is passed as its first argument.
+    /// The call is still to fun_name, so s.find("x") is written back as str_find(s, "x").
+    pub fn register_method(&mut self, receiver: &VarType, method: &str, fun_name: &str) {
+        let fun = self.functions[fun_name].clone();
+
+        self.methods.insert(receiver, method, fun);
+    }
+
+    /// The methods of each type
+    pub fn methods(&self) -> &MethodTable {
+        &self.methods
+    }
+
+    /// Removes a built-in, along with any methods that call it, returning it if it was registered
+    #[cfg(test)]
+    pub fn remove(&mut self, name: &str) -> Option<Function> {
+        self.methods.methods.retain(|_, fun| fun.name != name);
+        self.functions.remove(name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// The names of all the built-ins, sorted
+    #[cfg(any(test, feature = "lsp"))]
+    pub fn names(&self) -> Vec<&String> {
+        let mut names = self.functions.keys().collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+}
+
+/// A parameter of a built-in
+fn param(name: &str, var_def: VarDef) -> Variable {
+    Variable::from_def(name, var_def)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn str_def() -> VarDef {
+        VarDef::from_type(&VarType::String)
+    }
+
+    #[test]
+    fn registers_functions_and_methods() {
+        let mut registry = BuiltinRegistry::new();
+
+        registry.register("shout", vec![param("s", str_def())], Some(str_def())).is_noreturn = true;
+        registry.register_method(&VarType::String, "loud", "shout");
+
+        assert!(registry.contains("shout"));
+        assert!(registry.get("shout").unwrap().is_noreturn);
+        assert_eq!(registry.methods().get(&str_def(), "loud").map(|f| f.name.as_str()), Some("shout"));
+        assert!(registry.methods().get(&VarDef::from_array(&VarType::String), "loud").is_none());
+    }
+
+    #[test]
+    fn remove_drops_the_methods_calling_the_builtin() {
+        let mut registry = BuiltinRegistry::new();
+
+        registry.register("shout", vec![param("s", str_def())], Some(str_def()));
+        registry.register_method(&VarType::String, "loud", "shout");
+
+        assert_eq!(registry.remove("shout").map(|f| f.name), Some(String::from("shout")));
+        assert!(!registry.contains("shout"));
+        assert!(registry.methods().get(&str_def(), "loud").is_none());
+        assert!(registry.remove("shout").is_none());
+    }
+
+    #[test]
+    fn names_are_sorted() {
+        let mut registry = BuiltinRegistry::new();
+
+        for name in &["b", "c", "a"] {
+            registry.register(name, vec![], None);
+        }
+
+        assert_eq!(registry.names(), vec!["a", "b", "c"]);
+        assert!(BuiltinRegistry::standard().names().windows(2).all(|w| w[0] < w[1]));
+    }
+}
@@ -0,0 +1,78 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Running commands, and the arguments passed to the script
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+
+    registry.register("run", vec![param("input", VarDef::from_type(&VarType::Pipe)), param("exec", str_type())], Some(VarDef::from_array(&VarType::Pipe)));
+
+    // runs the command to completion with its output buffered, for when streaming it is not needed. The map has
+    // the keys "status", "stdout", and "stderr"; map values have one type, so the status is its decimal text
+    registry.register("capture", vec![param("input", VarDef::from_type(&VarType::Pipe)), param("exec", str_type())], Some(VarDef::from_type(&VarType::Map(Box::new(VarType::String)))));
+
+    // reruns a command that exits non-zero, sleeping delay_secs between attempts, and fails once
+    // every attempt has; retry_fun does the same for a function, retrying while it fails
+    let num_type = || VarDef::from_type(&VarType::Number);
+    let attempts = || vec![param("attempts", num_type()), param("delay_secs", num_type())];
+
+    registry.register("retry", [attempts(), vec![param("exec", str_type())]].concat(), Some(VarDef::from_type(&VarType::Pipe)));
+    registry.register("retry_fun", [attempts(), vec![param("f", VarDef::from_type(&VarType::Fun(vec![], Some(Box::new(VarDef::from_type(&VarType::Pipe))))))]].concat(), Some(VarDef::from_type(&VarType::Pipe)));
+
+    // explicit, lossless conversions between the output of a command and its lines, empty lines included
+    let pipe_type = || VarDef::from_type(&VarType::Pipe);
+    let lines = || VarDef::from_array(&VarType::String);
+
+    registry.register("pipe_from_lines", vec![param("lines", lines())], Some(pipe_type()));
+    registry.register("lines_from_pipe", vec![param("p", pipe_type())], Some(lines()));
+    registry.register("collect", vec![param("p", pipe_type())], Some(str_type())); // the whole output as one string
+    registry.register("pipe_concat", vec![param("first", pipe_type()), param("second", pipe_type())], Some(pipe_type()));
+
+    // grouping and reshaping lines, each of which gives an empty result for an empty pipe
+    let num_type = || VarDef::from_type(&VarType::Number);
+
+    registry.register("pipe_dedup_consecutive", vec![param("p", pipe_type())], Some(pipe_type())); // like uniq
+    registry.register("pipe_group_by_prefix", vec![param("p", pipe_type()), param("delimiter", str_type())], Some(VarDef::from_type(&VarType::Map(Box::new(VarType::Pipe))))); // keyed by the text before the first delimiter
+    registry.register("pipe_interleave", vec![param("first", pipe_type()), param("second", pipe_type())], Some(pipe_type())); // alternates lines, then the rest of the longer one
+    registry.register("pipe_chunk", vec![param("p", pipe_type()), param("size", num_type())], Some(VarDef::from_array(&VarType::Pipe)));
+
+    // arrays don't nest, so each window of size lines is a pipe of its own, rather than a str[]
+    registry.register("pipe_sliding_window", vec![param("p", pipe_type()), param("size", num_type())], Some(VarDef::from_array(&VarType::Pipe)));
+
+    // the named group of the pattern in each line that matches, and a map of every named group in each line that matches.
+    // Lines that don't match, or where the group matched nothing, are left out.
+    registry.register("pipe_match", vec![param("p", pipe_type()), param("pattern", str_type()), param("group", str_type())], Some(lines()));
+    registry.register("pipe_match_all_groups", vec![param("p", pipe_type()), param("pattern", str_type())], Some(VarDef::from_array(&VarType::Map(Box::new(VarType::String)))));
+
+    // the methods of pipes, which a pipe array calls on each of its pipes, like ps.run("wc -l")
+    let methods = [
+        ("run", "run"), ("capture", "capture"), ("collect", "collect"), ("lines", "lines_from_pipe"), ("concat", "pipe_concat"),
+        ("dedup_consecutive", "pipe_dedup_consecutive"), ("group_by_prefix", "pipe_group_by_prefix"), ("interleave", "pipe_interleave"),
+        ("chunk", "pipe_chunk"), ("sliding_window", "pipe_sliding_window")
+    ];
+
+    for (method, fun_name) in methods.iter() {
+        registry.register_method(&VarType::Pipe, method, fun_name);
+    }
+
+    // the script's own standard input, so it can sit in a shell pipeline; every call returns the
+    // same stream, so reading it twice continues where the first read stopped rather than losing data
+    registry.register("stdin", vec![], Some(pipe_type()));
+
+    // the script's own standard output and error, which can only be written to, as in write_to_pipe(p, stderr())
+    registry.register("stdout", vec![], Some(pipe_type()));
+    registry.register("stderr", vec![], Some(pipe_type()));
+    registry.register("write_to_pipe", vec![param("p", pipe_type()), param("sink", pipe_type())], None);
+    registry.register_method(&VarType::Pipe, "write_to_pipe", "write_to_pipe"); // lines.write_to_pipe(stderr())
+
+    // the arguments passed to the script after -- on the command line
+    registry.register("args", vec![], Some(VarDef::from_array(&VarType::String)));
+
+    // parallel execution built-ins, results are in the same order as the commands
+    let commands = || param("commands", VarDef::from_array(&VarType::String));
+
+    registry.register("pipe_parallel_exec", vec![commands()], Some(VarDef::from_array(&VarType::Pipe)));
+    registry.register("pipe_parallel_exec_n", vec![commands(), param("max_concurrent", VarDef::from_type(&VarType::Number))], Some(VarDef::from_array(&VarType::Pipe)));
+    registry.register("pipe_parallel_status", vec![commands()], Some(VarDef::from_array(&VarType::Number)));
+}
@@ -0,0 +1,23 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Managing this process and the processes it starts
+pub fn register(registry: &mut BuiltinRegistry) {
+    let num_type = || VarDef::from_type(&VarType::Number);
+    let pid = || param("pid", num_type());
+
+    registry.register("process_get_pid", vec![], Some(num_type()));
+
+    let exit = registry.register("process_exit", vec![param("code", num_type())], None);
+
+    exit.is_noreturn = true;
+    exit.defaults = vec![String::from("0")];
+
+    registry.register("process_spawn", vec![param("args", VarDef::from_array(&VarType::String))], Some(num_type()));
+    registry.register("process_wait", vec![pid()], Some(num_type()));
+    registry.register("process_kill", vec![pid()], None);
+
+    // the path of the program a command would run, searching the PATH, or "" when there is none
+    registry.register("which", vec![param("cmd", VarDef::from_type(&VarType::String))], Some(VarDef::from_type(&VarType::String)));
+}
@@ -0,0 +1,20 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Statistics over arrays of numbers. An empty array has no minimum, maximum, mean, median, standard
+/// deviation, or percentile, so those return an array with at most one element, while its sum is 0.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let nums = || param("nums", VarDef::from_array(&VarType::Number));
+    let opt_num = || VarDef::from_array(&VarType::Number);
+
+    registry.register("sum_of_arr", vec![nums()], Some(VarDef::from_type(&VarType::Number)));
+
+    // the standard deviation is of the whole population, not a sample of it
+    for name in &["min_of_arr", "max_of_arr", "avg_of_arr", "median_of_arr", "stdev_of_arr"] {
+        registry.register(name, vec![nums()], Some(opt_num()));
+    }
+
+    // percentile is from 0 to 100, interpolating between the two closest values
+    registry.register("percentile_of_arr", vec![nums(), param("percentile", VarDef::from_type(&VarType::Number))], Some(opt_num()));
+}
@@ -0,0 +1,66 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Plain substring searching, for when a regex is more than is needed, and counting like wc. Indices are
+/// byte offsets and line numbers start at 1. There is no optional type, so a search that can come up empty
+/// returns an array with at most one element, and no bool type, so predicates return 1 or 0.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+    let num_type = || VarDef::from_type(&VarType::Number);
+    let search = || vec![param("haystack", str_type()), param("needle", str_type())];
+
+    registry.register("str_find", search(), Some(VarDef::from_array(&VarType::Number)));
+    registry.register("str_find_all", search(), Some(VarDef::from_array(&VarType::Number)));
+    registry.register("str_count", search(), Some(num_type())); // non-overlapping occurrences
+
+    registry.register("str_index_to_line", vec![param("s", str_type()), param("index", num_type())], Some(num_type()));
+    registry.register("str_line_at", vec![param("s", str_type()), param("line", num_type())], Some(VarDef::from_array(&VarType::String)));
+
+    // counts for replacing wc: line_count is the number of newlines plus one, words are separated
+    // by whitespace, and a char is a Unicode scalar value rather than a byte
+    for name in &["line_count", "word_count", "char_count", "byte_count"] {
+        registry.register(name, vec![param("s", str_type())], Some(num_type()));
+    }
+
+    for name in &["pipe_word_count", "pipe_char_count"] {
+        registry.register(name, vec![param("p", VarDef::from_type(&VarType::Pipe))], Some(num_type()));
+    }
+
+    // also the word_count and char_count methods of a pipe, like p.word_count()
+    registry.register_method(&VarType::Pipe, "word_count", "pipe_word_count");
+    registry.register_method(&VarType::Pipe, "char_count", "pipe_char_count");
+
+    for name in &["str_is_empty", "str_is_whitespace", "str_is_numeric"] {
+        registry.register(name, vec![param("s", str_type())], Some(num_type()));
+    }
+
+    // safe conversions, empty when the string is not a number, a whole number, or one of true, false, 1, 0,
+    // yes, or no in any case; str_parse_bool returns 1 or 0
+    for name in &["str_parse_num", "str_parse_int", "str_parse_bool"] {
+        registry.register(name, vec![param("s", str_type())], Some(VarDef::from_array(&VarType::Number)));
+    }
+
+    // removes every newline at the end, like $(...) in a shell does to the output of a command
+    registry.register("str_trim_newlines", vec![param("s", str_type())], Some(str_type()));
+
+    // a char is a Unicode scalar value, and its code is that value as a num
+    let char_type = || VarDef::from_type(&VarType::Char);
+
+    registry.register("char_to_str", vec![param("c", char_type())], Some(str_type()));
+    registry.register("char_to_code", vec![param("c", char_type())], Some(num_type()));
+    registry.register("str_chars", vec![param("s", str_type())], Some(VarDef::from_array(&VarType::Char)));
+
+    // the search and count built-ins are also methods of str, like s.find("x")
+    let methods = [
+        ("find", "str_find"), ("find_all", "str_find_all"), ("count", "str_count"),
+        ("index_to_line", "str_index_to_line"), ("line_at", "str_line_at"),
+        ("line_count", "line_count"), ("word_count", "word_count"), ("char_count", "char_count"), ("byte_count", "byte_count"),
+        ("is_empty", "str_is_empty"), ("is_whitespace", "str_is_whitespace"), ("is_numeric", "str_is_numeric"),
+        ("chars", "str_chars"), ("trim_newlines", "str_trim_newlines")
+    ];
+
+    for (method, fun_name) in methods.iter() {
+        registry.register_method(&VarType::String, method, fun_name);
+    }
+}
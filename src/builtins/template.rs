@@ -0,0 +1,14 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Text templating, replacing each {{key}} in a template with the value of key in vars.
+/// A key that is not in vars fails the script when it is run, rather than rendering as empty.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+    let vars = || param("vars", VarDef::from_type(&VarType::Map(Box::new(VarType::String))));
+
+    registry.register("template_render", vec![param("template", str_type()), vars()], Some(str_type()));
+    registry.register("template_read_render", vec![param("path", str_type()), vars()], Some(str_type())); // reads the template from a file
+    registry.register("template_render_to_file", vec![param("template", str_type()), vars(), param("output", str_type())], None);
+}
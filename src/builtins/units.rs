@@ -0,0 +1,18 @@
+use crate::expression::{VarDef, VarType};
+
+use super::{BuiltinRegistry, param};
+
+/// Durations in seconds and sizes in bytes, read from and written as text like "1d2h" or "1.5GiB".
+/// See units::parse_duration and units::parse_size for what is accepted. A literal passed to a parse
+/// function is checked when the script is parsed, anything else when it runs.
+pub fn register(registry: &mut BuiltinRegistry) {
+    let str_type = || VarDef::from_type(&VarType::String);
+    let num_type = || VarDef::from_type(&VarType::Number);
+
+    registry.register("parse_duration", vec![param("duration", str_type())], Some(num_type()));
+    registry.register("parse_size", vec![param("size", str_type())], Some(num_type()));
+
+    // the largest units that fit, so parsing the result gives back the same number, like "1d2h" and "1.5GiB"
+    registry.register("format_duration", vec![param("secs", num_type())], Some(str_type()));
+    registry.register("format_size", vec![param("bytes", num_type())], Some(str_type()));
+}
@@ -0,0 +1,52 @@
+/// A machine-readable view of a ParseError or Warning
+#[derive(Debug, Clone)]
+pub struct Diagnostic<'a> {
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    pub severity: &'static str,
+    pub code: &'static str,
+    pub source: &'a str,
+    pub message: &'a str
+}
+
+impl<'a> Diagnostic<'a> {
+    /// Renders the diagnostic as a single line JSON object
+    pub fn to_json(&self, file: &str) -> String {
+        format!("{{\"file\":{},\"line\":{},\"column\":{},\"end_line\":{},\"end_column\":{},\"severity\":{},\"code\":{},\"message\":{}}}",
+                json_string(file), self.line, self.col, self.end_line, self.end_col,
+                json_string(self.severity), json_string(self.code), json_string(self.message))
+    }
+}
+
+/// The (line, col) just past text, when it starts at line and col, counting columns in chars as pest does
+pub fn end_of(line: usize, col: usize, text: &str) -> (usize, usize) {
+    match text.rfind('\n') {
+        Some(i) => (line + text.matches('\n').count(), text[i + 1..].chars().count() + 1),
+        None => (line, col + text.chars().count())
+    }
+}
+
+/// Quotes and escapes a string for use in JSON
+pub fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+
+    ret.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c)
+        }
+    }
+
+    ret.push('"');
+
+    ret
+}
@@ -0,0 +1,112 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// Why an exec string could not be split into arguments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitError {
+    UnterminatedQuote(char, usize), // the quote character, and its byte offset
+    TrailingBackslash
+}
+
+impl fmt::Display for SplitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SplitError::UnterminatedQuote(q, pos) => write!(f, "unterminated {} quote starting at offset {}", if *q == '"' { "double" } else { "single" }, pos),
+            SplitError::TrailingBackslash => write!(f, "trailing backslash")
+        }
+    }
+}
+
+/// Splits the exec string of run into arguments, the way a shell would without any expansion:
+/// - unquoted whitespace separates arguments
+/// - single quotes keep everything up to the next single quote
+/// - double quotes keep everything up to the next double quote, except \" and \\ are escapes
+/// - outside of quotes, a backslash keeps the character after it
+///
+/// Quoted and unquoted text next to each other is one argument, so `a"b c"'d'` is `ab cd`,
+/// and `""` is an empty argument.
+pub fn split(exec: &str) -> Result<Vec<String>, SplitError> {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false; // true once arg has started, even if it is still empty
+    let mut chars = exec.char_indices();
+
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            },
+            '\'' => {
+                in_arg = true;
+
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => arg.push(c),
+                        None => return Err(SplitError::UnterminatedQuote('\'', pos))
+                    }
+                }
+            },
+            '"' => {
+                in_arg = true;
+
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) if c == '"' || c == '\\' => arg.push(c),
+                            Some((_, c)) => { arg.push('\\'); arg.push(c); },
+                            None => return Err(SplitError::UnterminatedQuote('"', pos))
+                        },
+                        Some((_, c)) => arg.push(c),
+                        None => return Err(SplitError::UnterminatedQuote('"', pos))
+                    }
+                }
+            },
+            '\\' => {
+                in_arg = true;
+
+                match chars.next() {
+                    Some((_, c)) => arg.push(c),
+                    None => return Err(SplitError::TrailingBackslash)
+                }
+            },
+            c => {
+                in_arg = true;
+                arg.push(c);
+            }
+        }
+    }
+
+    if in_arg {
+        args.push(arg);
+    }
+
+    Ok(args)
+}
+
+/// Finds the file a program of an exec string runs, like the which command: a program with a / in it is
+/// a path used as is, otherwise each directory of path, separated by colons, is searched in order.
+/// Returns None when there is no such executable file.
+pub fn which(program: &str, path: &str) -> Option<PathBuf> {
+    if program.contains('/') {
+        return Some(PathBuf::from(program)).filter(|p| is_executable(p));
+    }
+
+    path.split(':').filter(|dir| !dir.is_empty()).map(|dir| Path::new(dir).join(program)).find(|p| is_executable(p))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    matches!(path.metadata(), Ok(m) if m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
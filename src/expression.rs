@@ -1,51 +1,291 @@
 use super::Rule;
 use pest::iterators::Pair;
-
-use crate::script::SymbolTable;
+use serde::{Serialize, Deserialize};
 
 use std::fmt;
 
 /// An expression is either an assignment or a function call
 /// - an assignment to a variable
+/// - an assignment to a key of a map
 /// - a function that must be called
-#[derive(Debug, Clone)]
+/// - a choice between two blocks of code, of which only one runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Expression {
-    Assignment(String, Assignment),
-    FunctionCall(String, FunctionCall)
+    Assignment(Location, Assignment),
+    IndexAssignment(Location, IndexAssignment),
+    FunctionCall(Location, FunctionCall),
+    Conditional(Location, ConditionalExpr)
+}
+
+impl Expression {
+    /// The location in the source this expression was generated from
+    pub fn location(&self) -> &Location {
+        match self {
+            Expression::Assignment(loc, _) => loc,
+            Expression::IndexAssignment(loc, _) => loc,
+            Expression::FunctionCall(loc, _) => loc,
+            Expression::Conditional(loc, _) => loc
+        }
+    }
+
+    pub fn location_mut(&mut self) -> &mut Location {
+        match self {
+            Expression::Assignment(loc, _) => loc,
+            Expression::IndexAssignment(loc, _) => loc,
+            Expression::FunctionCall(loc, _) => loc,
+            Expression::Conditional(loc, _) => loc
+        }
+    }
+
+    /// All of the variables read by this expression, including those read in the branches of a conditional
+    pub fn operands(&self) -> Vec<&Variable> {
+        match self {
+            Expression::Assignment(_, a) => a.rhs.operands(),
+            Expression::IndexAssignment(_, ia) => vec![&ia.map, &ia.key, &ia.value],
+            Expression::FunctionCall(_, fc) => fc.var_list.iter().collect(),
+            Expression::Conditional(_, c) => {
+                let branches = c.then_code.iter().chain(c.else_code.iter()).flat_map(|e| e.operands());
+
+                std::iter::once(&c.cond).chain(branches).collect()
+            }
+        }
+    }
+
+    /// This expression, followed by the expressions in the branches of a conditional, and of any conditionals in those
+    pub fn flatten(&self) -> Vec<&Expression> {
+        let mut all = vec![self];
+
+        if let Expression::Conditional(_, c) = self {
+            all.extend(c.then_code.iter().chain(c.else_code.iter()).flat_map(|e| e.flatten()));
+        }
+
+        all
+    }
+
+    /// Every variable in this expression, both the ones written and the ones read
+    pub fn variables_mut(&mut self) -> Vec<&mut Variable> {
+        match self {
+            Expression::Assignment(_, a) => {
+                let mut vars = vec![&mut a.lhs];
+
+                match &mut a.rhs {
+                    RightHandSide::Variable(v) | RightHandSide::Term(Term::Variable(v)) => vars.push(v),
+                    RightHandSide::Term(_) => { },
+                    RightHandSide::Operation(op1, _, op2) | RightHandSide::Index(op1, op2) => vars.extend(vec![op1, op2]),
+                    RightHandSide::FunctionCall(fc) => vars.extend(fc.var_list.iter_mut()),
+                    RightHandSide::MapLiteral(_, entries) => vars.extend(entries.iter_mut().map(|(_, v)| v)),
+                    RightHandSide::ArrayLiteral(_, elements) => vars.extend(elements.iter_mut())
+                }
+
+                vars
+            },
+            Expression::IndexAssignment(_, ia) => vec![&mut ia.map, &mut ia.key, &mut ia.value],
+            Expression::FunctionCall(_, fc) => fc.var_list.iter_mut().collect(),
+            Expression::Conditional(_, c) => {
+                let branches = c.then_code.iter_mut().chain(c.else_code.iter_mut()).flat_map(|e| e.variables_mut());
+
+                std::iter::once(&mut c.cond).chain(branches).chain(std::iter::once(&mut c.result)).collect()
+            }
+        }
+    }
+
+    /// The location of this expression, and those of the expressions in the branches of a conditional
+    pub fn locations_mut(&mut self) -> Vec<&mut Location> {
+        match self {
+            Expression::Conditional(loc, c) => {
+                let branches = c.then_code.iter_mut().chain(c.else_code.iter_mut()).flat_map(|e| e.locations_mut());
+
+                std::iter::once(loc).chain(branches).collect()
+            },
+            e => vec![e.location_mut()]
+        }
+    }
+
+    /// Replaces the single read of temp with value, returning false if value cannot be placed there.
+    /// Any right-hand-side can be assigned directly, but operands and arguments must be variables.
+    pub fn inline(&mut self, temp: &Variable, value: &RightHandSide) -> bool {
+        if let Expression::Assignment(_, a) = self {
+            if let RightHandSide::Variable(v) = &a.rhs {
+                if v.name == temp.name {
+                    a.rhs = value.clone();
+                    return true;
+                }
+            }
+        }
+
+        let src = match value {
+            RightHandSide::Variable(v) => v,
+            _ => return false
+        };
+
+        let operands = match self {
+            Expression::Assignment(_, Assignment { rhs: RightHandSide::Operation(op1, _, op2), .. }) => vec![op1, op2],
+            Expression::Assignment(_, Assignment { rhs: RightHandSide::Index(_, key), .. }) => vec![key],
+            Expression::Assignment(_, Assignment { rhs: RightHandSide::MapLiteral(_, entries), .. }) => entries.iter_mut().map(|(_, v)| v).collect(),
+            Expression::Assignment(_, Assignment { rhs: RightHandSide::ArrayLiteral(_, elements), .. }) => elements.iter_mut().collect(),
+            Expression::Conditional(_, c) => vec![&mut c.cond],
+            Expression::IndexAssignment(_, ia) => vec![&mut ia.key, &mut ia.value],
+            Expression::Assignment(_, Assignment { rhs: RightHandSide::FunctionCall(fc), .. }) |
+            Expression::FunctionCall(_, fc) => fc.var_list.iter_mut().collect(),
+            _ => vec![]
+        };
+
+        let mut replaced = false;
+
+        for operand in operands.into_iter().filter(|v| v.name == temp.name) {
+            *operand = src.clone();
+            replaced = true;
+        }
+
+        replaced
+    }
 }
 
 impl fmt::Display for Expression {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            Expression::Assignment(_, a) => { write!(f, "{}", a) },
-            Expression::FunctionCall(_, fc) => { write!(f, "{}", fc) }
+            Expression::Assignment(loc, a) => { write!(f, "{}: {}", loc, a) },
+            Expression::IndexAssignment(loc, ia) => { write!(f, "{}: {}", loc, ia) },
+            Expression::FunctionCall(loc, fc) => { write!(f, "{}: {}", loc, fc) },
+            Expression::Conditional(loc, c) => { write!(f, "{}: {}", loc, c) }
+        }
+    }
+}
+
+/// The source text an expression was generated from, and where it is in the file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Location {
+    pub file: String, // empty when the script was not read from a file
+    pub line: usize,
+    pub col: usize,
+    pub end_line: usize,
+    pub text: String
+}
+
+impl Location {
+    pub fn from_pair(pair: &Pair<Rule>) -> Location {
+        let span = pair.as_span();
+        let (line, col) = span.start_pos().line_col();
+        let end_line = span.end_pos().line_col().0;
+
+        Location { file: String::new(), line, col, end_line, text: String::from(span.as_str()) }
+    }
+
+    /// Returns true if the given line falls within this location
+    #[cfg(test)]
+    pub fn contains_line(&self, line: usize) -> bool {
+        self.line <= line && line <= self.end_line
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.file.is_empty() {
+            write!(f, "{}:{}", self.line, self.col)
+        } else {
+            write!(f, "{}:{}:{}", self.file, self.line, self.col)
         }
     }
 }
 
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum VarType {
     String,
+    Char,
     Number,
-    Pipe
+    Pipe,
+    Map(Box<VarType>),                // string keys, and values of the boxed type
+    Fun(Vec<VarDef>, Option<Box<VarDef>>) // a function with the types of its params and return value
 }
 
-#[derive(Clone, Debug, PartialEq)]
+impl VarType {
+    /// The name of the type without its parts, like "map" for every map, used to look up its methods
+    pub fn canonical_name(&self) -> &'static str {
+        match self {
+            VarType::String => "str",
+            VarType::Char => "char",
+            VarType::Number => "num",
+            VarType::Pipe => "pipe",
+            VarType::Map(_) => "map",
+            VarType::Fun(..) => "fun"
+        }
+    }
+
+    /// Given a var_type rule, constructs the type, like "map[str]" or "fun(str) -> num"
+    pub fn new(var_type: Pair<Rule>) -> VarType {
+        if let Some(inner) = var_type.clone().into_inner().next() {
+            if inner.as_rule() == Rule::map_type {
+                return VarType::Map(Box::new(VarType::new(inner.into_inner().next().unwrap())));
+            }
+
+            let mut params = Vec::new();
+            let mut ret = None;
+
+            for p in inner.into_inner() {
+                match p.as_rule() {
+                    Rule::fun_type_ret => ret = Some(Box::new(VarDef::new(p.into_inner().next().unwrap()))),
+                    _ => params.push(VarDef::new(p))
+                }
+            }
+
+            return VarType::Fun(params, ret);
+        }
+
+        match var_type.as_str() {
+            "str" => { VarType::String },
+            "char" => { VarType::Char },
+            "num" => { VarType::Number },
+            "pipe" => { VarType::Pipe },
+            _ => { panic!("Unknown variable type: {:?}", var_type) }
+        }
+    }
+
+    /// The type of the values when this is a map, otherwise None
+    pub fn map_value(&self) -> Option<&VarType> {
+        match self {
+            VarType::Map(value) => Some(value),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for VarType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VarType::String => write!(f, "str"),
+            VarType::Char => write!(f, "char"),
+            VarType::Number => write!(f, "num"),
+            VarType::Pipe => write!(f, "pipe"),
+            VarType::Map(value) => write!(f, "map[{}]", value),
+            VarType::Fun(params, ret) => {
+                let params = params.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+                match ret {
+                    Some(ret) => write!(f, "fun({}) -> {}", params.join(", "), ret),
+                    None => write!(f, "fun({})", params.join(", "))
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct VarDef {
     pub var_type:VarType,
     pub is_array:bool
 }
 
+impl fmt::Display for VarDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.var_type, if self.is_array { "[]" } else { "" })
+    }
+}
+
 impl VarDef {
     pub fn new(var_def: Pair<Rule>) -> VarDef {
-        let mut inner = var_def.clone().into_inner();
+        let mut inner = var_def.into_inner();
 
-        let var_type = match inner.next().unwrap().as_str() {
-            "str" => { VarType::String },
-            "num" => { VarType::Number },
-            "pipe" => { VarType::Pipe },
-            _ => { panic!("Unknown variable type: {:?}", var_def) }
-        };
+        let var_type = VarType::new(inner.next().unwrap());
 
         VarDef { var_type, is_array: inner.peek().is_some() }
     }
@@ -57,30 +297,72 @@ impl VarDef {
     pub fn from_array(var_type: &VarType) -> VarDef {
         VarDef{ var_type:var_type.clone(), is_array:true }
     }
+
+    /// Returns true if a value of this type can be passed where other is expected: the same
+    /// type, or a char where a str is expected, which is converted to a one character str
+    pub fn is_compatible_with(&self, other: &VarDef) -> bool {
+        self == other || (self.var_type == VarType::Char && other.var_type == VarType::String && self.is_array == other.is_array)
+    }
+}
+
+/// The (line, col, length) of a name where it was declared in the source
+pub type DeclSpan = (usize, usize, usize);
+
+/// Returns the DeclSpan of a pair
+pub fn decl_span(pair: &Pair<Rule>) -> DeclSpan {
+    let span = pair.as_span();
+    let (line, col) = span.start_pos().line_col();
+
+    (line, col, span.end() - span.start())
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Variable {
     pub name:String,
-    pub var_def:VarDef
+    pub var_def:VarDef,
+    pub decl_span:Option<DeclSpan>, // where the variable was declared, None for built-in params and temps
+    pub is_const:bool               // declared with const, so it is never assigned again
 }
 
 impl Variable {
+    /// Returns true if this is a temp generated while processing expressions
+    pub fn is_temp(&self) -> bool {
+        self.name.starts_with("_t")
+    }
+
     /// Given a var_dec rule, constructs a variable
     pub fn new(var_dec: Pair<Rule>) -> Variable {
         let mut inner = var_dec.into_inner();
-        let name = String::from(inner.next().unwrap().as_str());
+        let ident = inner.next().unwrap();
+        let name = String::from(ident.as_str());
 
         let var_def = VarDef::new(inner.next().unwrap());
 
         Variable {
             name,
-            var_def
+            var_def,
+            decl_span: Some(decl_span(&ident)),
+            is_const: false
         }
     }
+
+    /// The keyword that declares the variable, var or const
+    pub fn keyword(&self) -> &'static str {
+        if self.is_const { "const" } else { "var" }
+    }
+
+    /// The variable as it is declared, like "x:str" or "items:num[]"
+    pub fn display_decl(&self) -> String {
+        format!("{}:{}", self.name, self.var_def)
+    }
+
+    /// Constructs a variable that was not declared in the source, like a built-in's parameter
+    pub fn from_def(name: &str, var_def: VarDef) -> Variable {
+        Variable { name: String::from(name), var_def, decl_span: None, is_const: false }
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Operator {
     Add,
     Sub,
@@ -88,15 +370,80 @@ pub enum Operator {
     Div
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Sub => write!(f, "-"),
+            Operator::Mul => write!(f, "*"),
+            Operator::Div => write!(f, "/")
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RightHandSide {
     Variable(Variable),
     Term(Term),
     Operation(Variable, Operator, Variable),
-    FunctionCall(FunctionCall)
+    FunctionCall(FunctionCall),
+    MapLiteral(VarType, Vec<(String, Variable)>), // the type of the values, and each key, with its quotes, and value
+    ArrayLiteral(VarType, Vec<Variable>),         // the type of the elements, and each element
+    Index(Variable, Variable)                     // the map and the key read from it
 }
 
-#[derive(Debug, Clone)]
+impl RightHandSide {
+    /// All of the variables read by this right-hand-side
+    pub fn operands(&self) -> Vec<&Variable> {
+        match self {
+            RightHandSide::Variable(v) => vec![v],
+            RightHandSide::Term(Term::Variable(v)) => vec![v],
+            RightHandSide::Term(_) => vec![],
+            RightHandSide::Operation(op1, _, op2) => vec![op1, op2],
+            RightHandSide::FunctionCall(fc) => fc.var_list.iter().collect(),
+            RightHandSide::MapLiteral(_, entries) => entries.iter().map(|(_, v)| v).collect(),
+            RightHandSide::ArrayLiteral(_, elements) => elements.iter().collect(),
+            RightHandSide::Index(map, key) => vec![map, key]
+        }
+    }
+
+    pub fn to_source(&self) -> String {
+        self.to_source_with(&|v| v.name.clone())
+    }
+
+    /// Renders the right-hand-side as source, using operand to render each variable read
+    pub fn to_source_with(&self, operand: &dyn Fn(&Variable) -> String) -> String {
+        match self {
+            RightHandSide::Variable(v) => operand(v),
+            RightHandSide::Term(Term::Variable(v)) => operand(v),
+            RightHandSide::Term(t) => t.to_source(),
+            RightHandSide::Operation(op1, op, op2) => format!("{} {} {}", operand(op1), op, operand(op2)),
+            RightHandSide::FunctionCall(fc) => fc.to_source_with(operand),
+            RightHandSide::MapLiteral(_, entries) => {
+                let entries = entries.iter().map(|(k, v)| format!("{}: {}", k, operand(v))).collect::<Vec<_>>();
+
+                format!("{{{}}}", entries.join(", "))
+            },
+            RightHandSide::ArrayLiteral(_, elements) => format!("[{}]", elements.iter().map(operand).collect::<Vec<_>>().join(", ")),
+            RightHandSide::Index(map, key) => format!("{}[{}]", operand(map), operand(key))
+        }
+    }
+
+    /// The type of the value produced, or None for a call to a function without a return type
+    pub fn var_def(&self) -> Option<VarDef> {
+        match self {
+            RightHandSide::Variable(v) => Some(v.var_def.clone()),
+            RightHandSide::Operation(v, _, _) => Some(v.var_def.clone()),
+            RightHandSide::Term(t) => Some(t.var_def()),
+            RightHandSide::FunctionCall(f) => f.fun.ret_type.clone(),
+            RightHandSide::MapLiteral(value, _) => Some(VarDef::from_type(&VarType::Map(Box::new(value.clone())))),
+            RightHandSide::ArrayLiteral(element, _) => Some(VarDef::from_array(element)),
+            RightHandSide::Index(map, _) => map.var_def.var_type.map_value().map(VarDef::from_type)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Assignment {
     pub lhs:Variable,
     pub rhs:RightHandSide
@@ -108,10 +455,74 @@ impl fmt::Display for Assignment {
     }
 }
 
-#[derive(Debug, Clone)]
+/// cond ? then : else, where only the code of the branch chosen runs, so either can run commands.
+/// The code of each branch ends by assigning its value to result.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConditionalExpr {
+    pub cond:Variable,               // a num, the then branch runs when it is not 0
+    pub then_code:Vec<Expression>,
+    pub else_code:Vec<Expression>,
+    pub result:Variable              // the temp both branches assign their value to
+}
+
+impl fmt::Display for ConditionalExpr {
+    /// Writes the conditional like "c ? { _t2 = a } : { _t2 = b }"
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let branch = |code: &Vec<Expression>| code.iter().map(|e| match e {
+            Expression::Assignment(_, a) => a.to_string(),
+            Expression::IndexAssignment(_, ia) => ia.to_string(),
+            Expression::FunctionCall(_, fc) => fc.to_string(),
+            Expression::Conditional(_, c) => c.to_string()
+        }).collect::<Vec<_>>().join("; ");
+
+        write!(f, "{} ? {{ {} }} : {{ {} }}", self.cond.name, branch(&self.then_code), branch(&self.else_code))
+    }
+}
+
+/// An assignment to one key of a map, like m["key"] = value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexAssignment {
+    pub map:Variable,
+    pub key:Variable,
+    pub value:Variable
+}
+
+impl IndexAssignment {
+    pub fn to_source(&self) -> String {
+        format!("{}[{}] = {}", self.map.name, self.key.name, self.value.name)
+    }
+}
+
+impl fmt::Display for IndexAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_source())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionCall {
     pub fun:Function,
-    pub var_list:Vec<Variable>
+    pub var_list:Vec<Variable>, // the arguments, in the order of the params, those left over for a variadic param last
+    pub named:Vec<usize>,       // the params given by name, in the order they were written
+    pub broadcast:bool          // the first argument is a pipe[] passed to a pipe, so the call runs for each pipe
+}
+
+impl FunctionCall {
+    /// Renders the call as source with arg rendering each argument, like "foo(a, b, c)". Method calls are written as calls,
+    /// with the receiver first. Named arguments follow the positional ones, in the order they were written, like "copy(a, dest = b)".
+    pub fn to_source_with(&self, arg: &dyn Fn(&Variable) -> String) -> String {
+        let positional = (0..self.var_list.len()).filter(|i| !self.named.contains(i)).map(|i| arg(&self.var_list[i]));
+        let named = self.named.iter().map(|&i| format!("{} = {}", self.fun.params[i].name, arg(&self.var_list[i])));
+
+        format!("{}({})", self.fun.name, positional.chain(named).collect::<Vec<_>>().join(", "))
+    }
+
+    /// The call with every argument in the position of its param, like "copy(a, b)"
+    pub fn to_positional(&self) -> String {
+        let args = self.var_list.iter().map(|v| v.name.clone()).collect::<Vec<_>>();
+
+        format!("{}({})", self.fun.name, args.join(", "))
+    }
 }
 
 impl fmt::Display for FunctionCall {
@@ -121,36 +532,167 @@ impl fmt::Display for FunctionCall {
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Function {
     pub name: String,
-    pub params: SymbolTable,      // parameters to the function
+    pub params: Vec<Variable>,    // parameters to the function, in declaration order
     pub ret_type: Option<VarDef>, // return type of the function
+    pub defaults: Vec<String>,    // source of the default values of the last params, so callers can leave them out
+    pub is_variadic: bool,        // true if the last param is an array of the arguments left over
+    pub is_noreturn: bool,        // true if calling the function never returns, like process_exit
+    pub docstring: Option<String>, // the /// comments before the function, without the slashes
+    pub decl_span: Option<DeclSpan>, // where the function's name was declared, None for built-ins
     pub code: Vec<Expression>     // code that makes-up the function
 }
 
 impl Function {
     /// Constructs a new Function without checking to see if param names are duplicates
     pub fn new(name: &str, params: Vec<Variable>, ret: Option<VarDef>) -> Function {
-        let mut symbols = SymbolTable::new();
-
-        // put all the params into a symbol table
-        for param in params.iter() {
-            symbols.insert(param.clone().name, param.clone());
-        }
-
         Function {
             name: String::from(name),
-            params: symbols,
+            params,
             ret_type: ret,
+            defaults: Vec::new(),
+            is_variadic: false,
+            is_noreturn: false,
+            docstring: None,
+            decl_span: None,
             code: Vec::<Expression>::new()
         }
     }
+
+    /// The type of a value referencing this function, like "fun(str, num) -> pipe"
+    pub fn var_type(&self) -> VarType {
+        VarType::Fun(self.params.iter().map(|p| p.var_def.clone()).collect(), self.ret_type.clone().map(Box::new))
+    }
+
+    /// The function's signature as it is declared, like "fun name(a:str, b:num = 1) -> pipe"
+    /// or "fun name(a:str, rest:num...)"
+    pub fn display_header(&self) -> String {
+        format!("fun {}", self.display_signature())
+    }
+
+    /// The signature without the fun keyword, like "name(a:str, b:num = 1) -> pipe"
+    pub fn display_signature(&self) -> String {
+        let first_default = self.params.len() - self.defaults.len();
+
+        let mut params = self.params.iter().enumerate().map(|(i, p)| match i.checked_sub(first_default) {
+            Some(d) => format!("{} = {}", p.display_decl(), self.defaults[d]),
+            None => p.display_decl()
+        }).collect::<Vec<_>>();
+
+        if let (true, Some(rest)) = (self.is_variadic, self.params.last()) {
+            *params.last_mut().unwrap() = format!("{}:{}...", rest.name, rest.var_def.var_type);
+        }
+
+        match &self.ret_type {
+            Some(ret_type) => format!("{}({}) -> {}", self.name, params.join(", "), ret_type),
+            None => format!("{}({})", self.name, params.join(", "))
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Term {
     String(String),
+    Char(char),
     Number(f64),
-    Variable(Variable)
-}
\ No newline at end of file
+    Variable(Variable),
+    Function(String, VarDef) // a reference to a user function, and its type
+}
+
+impl Term {
+    /// Given the source of a char_literal, like 'a' or '\n', constructs the char it is
+    pub fn from_char_literal(literal: &str) -> Term {
+        let mut chars = literal[1..literal.len() - 1].chars();
+
+        Term::Char(match (chars.next(), chars.next()) {
+            (Some('\\'), Some('n')) => '\n',
+            (Some('\\'), Some('t')) => '\t',
+            (Some('\\'), Some(c)) => c,
+            (Some(c), _) => c,
+            (None, _) => panic!("Empty char literal: {}", literal)
+        })
+    }
+
+    /// Given the source of a heredoc, constructs the string it is. It is kept as a heredoc
+    /// with its quotes on lines of their own, without the indentation that was removed.
+    pub fn from_heredoc(literal: &str) -> Term {
+        Term::String(quote_heredoc(&heredoc_text(literal)))
+    }
+
+    pub fn to_source(&self) -> String {
+        match self {
+            Term::String(s) => s.clone(), // the quotes are kept from the source
+            Term::Char(c) => match c {
+                '\n' => String::from("'\\n'"),
+                '\t' => String::from("'\\t'"),
+                '\\' => String::from("'\\\\'"),
+                '\'' => String::from("'\\''"),
+                c => format!("'{}'", c)
+            },
+            Term::Number(n) => n.to_string(),
+            Term::Variable(v) => v.name.clone(),
+            Term::Function(name, _) => name.clone()
+        }
+    }
+
+    /// The type of the value
+    pub fn var_def(&self) -> VarDef {
+        match self {
+            Term::String(_) => VarDef::from_type(&VarType::String),
+            Term::Char(_) => VarDef::from_type(&VarType::Char),
+            Term::Number(_) => VarDef::from_type(&VarType::Number),
+            Term::Variable(v) => v.var_def.clone(),
+            Term::Function(_, var_def) => var_def.clone()
+        }
+    }
+}
+
+/// Returns the text of a string literal, the source of a Term::String, without its quotes
+pub fn unquote(literal: &str) -> String {
+    if literal.len() >= 6 && literal.starts_with("\"\"\"") {
+        heredoc_text(literal)
+    } else {
+        String::from(&literal[1..literal.len() - 1])
+    }
+}
+
+/// Quotes text as a string literal, which is a heredoc when it has a quote or spans lines
+pub fn quote(text: &str) -> String {
+    if text.contains('"') || text.contains('\n') {
+        quote_heredoc(text)
+    } else {
+        format!("\"{}\"", text)
+    }
+}
+
+/// Quotes text as a heredoc that reads back as exactly the text, its quotes on lines of their own
+pub fn quote_heredoc(text: &str) -> String {
+    format!("\"\"\"\n{}\n\"\"\"", text)
+}
+
+/// Returns the text of a heredoc's source. A line break right after the opening quotes is dropped, as is the last
+/// line when it holds nothing but the closing quotes. When the heredoc spans lines, the indentation common to its
+/// lines that are not blank, and to the closing quotes on a line of their own, is removed from each line.
+pub fn heredoc_text(literal: &str) -> String {
+    let body = &literal[3..literal.len() - 3];
+    let body = body.strip_prefix('\n').unwrap_or(body);
+    let mut lines = body.split('\n').collect::<Vec<_>>();
+
+    if lines.len() == 1 {
+        return String::from(body);
+    }
+
+    let indent_of = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let is_blank = |line: &str| line.trim().is_empty();
+
+    let closing = match lines.last() {
+        Some(last) if is_blank(last) => lines.pop().map(indent_of),
+        _ => None
+    };
+
+    let indent = lines.iter().filter(|l| !is_blank(l)).map(|l| indent_of(l)).chain(closing).min().unwrap_or(0);
+
+    lines.iter().map(|line| &line[indent.min(indent_of(line))..]).collect::<Vec<_>>().join("\n")
+}
@@ -0,0 +1,136 @@
+use pest::Parser;
+use pest::iterators::Pair;
+
+use crate::{Rule, SSSParser};
+use crate::diagnostics::json_string;
+
+/// The kind of token a highlighted span covers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Operator,
+    FunctionName,
+    Comment
+}
+
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Keyword => "keyword",
+            Category::Identifier => "identifier",
+            Category::Number => "number",
+            Category::String => "string",
+            Category::Operator => "operator",
+            Category::FunctionName => "function_name",
+            Category::Comment => "comment"
+        }
+    }
+}
+
+/// A byte range of the source and what it contains
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub category: Category
+}
+
+impl Span {
+    pub fn to_json(&self) -> String {
+        format!("{{\"start\":{},\"end\":{},\"category\":{}}}", self.start, self.end, json_string(self.category.name()))
+    }
+}
+
+/// Returns the highlighted spans of the source, in order, without building a Script.
+/// Source that does not parse yields no spans, as the grammar has no error recovery.
+pub fn highlight(source: &str) -> Vec<Span> {
+    let script = match SSSParser::parse(Rule::script, source) {
+        Ok(mut pairs) => pairs.next().unwrap(),
+        Err(_) => return Vec::new()
+    };
+
+    let mut spans = Vec::new();
+
+    classify(script, Rule::script, &mut spans);
+
+    // keywords, punctuation operators, and comments are not pairs, so scan the gaps between the tokens
+    let mut gaps = Vec::new();
+    let mut pos = 0;
+
+    for span in &spans {
+        gaps.push((pos, span.start));
+        pos = span.end;
+    }
+
+    gaps.push((pos, source.len()));
+
+    for (start, end) in gaps {
+        scan_gap(source, start, end, &mut spans);
+    }
+
+    spans.sort_by_key(|s| s.start);
+
+    spans
+}
+
+/// Walks the pairs, adding a span for every token
+fn classify(pair: Pair<Rule>, parent: Rule, spans: &mut Vec<Span>) {
+    let category = match pair.as_rule() {
+        Rule::identifier if parent == Rule::fun_call || parent == Rule::fun_signature || parent == Rule::labeled_block => Some(Category::FunctionName),
+        Rule::identifier => Some(Category::Identifier),
+        Rule::string | Rule::heredoc | Rule::interpolated_heredoc | Rule::char_literal => Some(Category::String),
+        Rule::number => Some(Category::Number),
+        Rule::bin_op => Some(Category::Operator),
+        Rule::doc_comment => Some(Category::Comment),
+        Rule::var_type | Rule::array => Some(Category::Keyword),
+        _ => None
+    };
+
+    if let Some(category) = category {
+        let span = pair.as_span();
+
+        spans.push(Span { start: span.start(), end: span.end(), category });
+        return;
+    }
+
+    let rule = pair.as_rule();
+
+    for inner in pair.into_inner() {
+        classify(inner, rule, spans);
+    }
+}
+
+/// Scans text between tokens for comments, keywords, and operators
+fn scan_gap(source: &str, start: usize, end: usize, spans: &mut Vec<Span>) {
+    let gap = &source[start..end];
+    let mut pos = 0;
+
+    while pos < gap.len() {
+        let rest = &gap[pos..];
+
+        let (len, category) = if rest.starts_with("//") {
+            (rest.find('\n').unwrap_or(rest.len()), Some(Category::Comment))
+        } else if rest.starts_with("/*") {
+            (rest.find("*/").map(|i| i + 2).unwrap_or(rest.len()), Some(Category::Comment))
+        } else if rest.starts_with("->") || rest.starts_with("<-") {
+            (2, Some(Category::Operator))
+        } else if rest.starts_with('=') || rest.starts_with('.') {
+            (1, Some(Category::Operator))
+        } else if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            let len = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+
+            (len, if matches!(&rest[..len], "var" | "const" | "fun") { Some(Category::Keyword) } else { None })
+        } else {
+            (rest.chars().next().unwrap().len_utf8(), None)
+        };
+
+        if let Some(category) = category {
+            spans.push(Span { start: start + pos, end: start + pos + len, category });
+        }
+
+        pos += len;
+    }
+}
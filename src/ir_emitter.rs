@@ -0,0 +1,83 @@
+use crate::expression::*;
+
+/// Renders expressions in a canonical three-address code format, one instruction per line:
+///
+/// ```text
+/// _t1 = "hello"
+/// _t2 = run(_t1)
+/// x = _t2
+/// ```
+pub struct IrEmitter {
+    output: String,
+    indent: usize
+}
+
+impl IrEmitter {
+    pub fn new() -> IrEmitter {
+        IrEmitter { output: String::new(), indent: 0 }
+    }
+
+    /// Emits a function signature followed by its code in an indented block
+    pub fn emit_function(&mut self, fun: &Function) {
+        self.emit_line(&format!("{} {{", fun.display_header()));
+        self.indent += 1;
+        self.emit_code(&fun.code);
+        self.indent -= 1;
+        self.emit_line("}");
+    }
+
+    /// Emits an entry point's label followed by its code in an indented block
+    pub fn emit_entry_point(&mut self, name: &str, code: &[Expression]) {
+        self.emit_line(&format!("@{} {{", name));
+        self.indent += 1;
+        self.emit_code(code);
+        self.indent -= 1;
+        self.emit_line("}");
+    }
+
+    pub fn emit_code(&mut self, code: &[Expression]) {
+        for e in code {
+            self.emit_expression(e);
+        }
+    }
+
+    pub fn emit_expression(&mut self, e: &Expression) {
+        // calls are written with their arguments in the order of the params, named or not
+        let line = match e {
+            Expression::Assignment(_, Assignment { lhs, rhs: RightHandSide::FunctionCall(fc) }) => format!("{} = {}", lhs.name, fc.to_positional()),
+            Expression::Assignment(_, a) => format!("{} = {}", a.lhs.name, a.rhs.to_source()),
+            Expression::IndexAssignment(_, ia) => ia.to_source(),
+            Expression::FunctionCall(_, fc) => fc.to_positional(),
+            Expression::Conditional(_, c) => return self.emit_conditional(c)
+        };
+
+        self.emit_line(&line);
+    }
+
+    /// Emits the code of each branch in an indented block, as "if cond { ... } else { ... }"
+    fn emit_conditional(&mut self, c: &ConditionalExpr) {
+        self.emit_line(&format!("if {} {{", c.cond.name));
+        self.indent += 1;
+        self.emit_code(&c.then_code);
+        self.indent -= 1;
+        self.emit_line("} else {");
+        self.indent += 1;
+        self.emit_code(&c.else_code);
+        self.indent -= 1;
+        self.emit_line("}");
+    }
+
+    /// Returns everything emitted so far
+    pub fn finish(self) -> String {
+        self.output
+    }
+
+    fn emit_line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.output.push_str("    ");
+        }
+
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+}
@@ -0,0 +1,25 @@
+use serde::{Serialize, Deserialize};
+
+use crate::expression::Expression;
+use crate::parse_error::{ParseError, ErrorKind};
+use crate::script::{FunctionTable, SymbolTable};
+
+/// The parsed functions and global variables of a script, saved as a .sssl file
+/// so a script that is imported often does not need to be parsed every time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Library {
+    pub functions: FunctionTable,
+    pub variables: SymbolTable,
+    pub code: Vec<Expression> // assigns the variables, and runs before the code of the script importing the library
+}
+
+impl Library {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Error serializing library")
+    }
+
+    /// Reads a library written by to_bytes, the path is only used to report errors
+    pub fn from_bytes(path: &str, bytes: &[u8]) -> Result<Library, ParseError> {
+        bincode::deserialize(bytes).map_err(|e| ParseError::without_location(ErrorKind::InvalidLibrary, path, format!("Invalid library {}: {}", path, e)))
+    }
+}
@@ -0,0 +1,263 @@
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use crate::diagnostics::Diagnostic;
+use crate::expression::DeclSpan;
+use crate::script::Script;
+use crate::script_builder::ScriptBuilder;
+
+type LspResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// An open document: its current text, and the script from the last version of it that parsed,
+/// so the symbols are still there while an edit is half done
+struct Document {
+    text: String,
+    script: Option<Script>
+}
+
+/// Serves the language server protocol over stdin and stdout, until the client asks it to exit
+pub fn serve() -> LspResult<()> {
+    let (connection, io_threads) = Connection::stdio();
+
+    run(&connection)?;
+    io_threads.join()?;
+
+    Ok( () )
+}
+
+/// Answers the client on the other end of connection: diagnostics each time a document is opened or changed,
+/// and the symbols, definitions, and completions of the open documents
+fn run(connection: &Connection) -> LspResult<()> {
+    connection.initialize(json!({
+        "textDocumentSync": 1, // the whole document is sent on each change
+        "documentSymbolProvider": true,
+        "definitionProvider": true,
+        "completionProvider": {}
+    }))?;
+
+    let mut documents = HashMap::<String, Document>::new(); // by uri
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok( () );
+                }
+
+                connection.sender.send(Message::Response(respond(&documents, request)))?;
+            },
+            Message::Notification(notification) => {
+                if let Some(uri) = update(&mut documents, notification) {
+                    let diagnostics = documents.get(&uri).map_or_else(Vec::new, |doc| diagnostics(&doc.text));
+                    let params = json!({ "uri": uri, "diagnostics": diagnostics });
+
+                    connection.sender.send(Message::Notification(Notification::new(String::from("textDocument/publishDiagnostics"), params)))?;
+                }
+            },
+            Message::Response(_) => { }
+        }
+    }
+
+    Ok( () )
+}
+
+/// Applies an open, change, or close of a document, returning its uri, or None for any other notification
+fn update(documents: &mut HashMap<String, Document>, notification: Notification) -> Option<String> {
+    let params = &notification.params;
+    let uri = params["textDocument"]["uri"].as_str()?.to_string();
+
+    let text = match notification.method.as_str() {
+        "textDocument/didOpen" => params["textDocument"]["text"].as_str()?,
+        "textDocument/didChange" => params["contentChanges"].as_array()?.last()?["text"].as_str()?,
+        "textDocument/didClose" => {
+            documents.remove(&uri);
+            return Some(uri);
+        },
+        _ => return None
+    };
+
+    let script = ScriptBuilder::new().parse_str(text).build().ok();
+    let doc = documents.entry(uri.clone()).or_insert(Document { text: String::new(), script: None });
+
+    doc.text = String::from(text);
+    doc.script = script.or_else(|| doc.script.take());
+
+    Some(uri)
+}
+
+fn respond(documents: &HashMap<String, Document>, request: Request) -> Response {
+    let params = &request.params;
+    let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+    let doc = documents.get(uri);
+    let script = doc.and_then(|d| d.script.as_ref());
+
+    let result = match request.method.as_str() {
+        "textDocument/documentSymbol" => json!(script.map_or_else(Vec::new, symbols)),
+        "textDocument/definition" => {
+            let name = doc.and_then(|d| word_at(&d.text, &params["position"]));
+            let span = script.zip(name).and_then(|(script, name)| script.find_declaration(name));
+
+            span.map_or(Value::Null, |span| json!({ "uri": uri, "range": decl_range(span) }))
+        },
+        "textDocument/completion" => json!(script.map_or_else(Vec::new, completions)),
+        _ => return Response::new_err(request.id, ErrorCode::MethodNotFound as i32, format!("Unknown method {}", request.method))
+    };
+
+    Response::new_ok(request.id, result)
+}
+
+/// The errors of the text, or its warnings when it has none
+fn diagnostics(text: &str) -> Vec<Value> {
+    let to_json = |d: Diagnostic| json!({
+        "range": range(d.line, d.col, d.end_line, d.end_col),
+        "severity": if d.severity == "error" { 1 } else { 2 },
+        "code": d.code,
+        "source": "sss",
+        "message": d.message
+    });
+
+    match ScriptBuilder::new().parse_str(text).build() {
+        Ok(script) => script.warnings().iter().map(|w| to_json(w.diagnostic())).collect(),
+        Err(errors) => errors.iter().map(|e| to_json(e.diagnostic())).collect()
+    }
+}
+
+/// The user functions and global variables of the script, in the order they are declared
+fn symbols(script: &Script) -> Vec<Value> {
+    const FUNCTION: u32 = 12; // the LSP's SymbolKind
+    const VARIABLE: u32 = 13;
+
+    let functions = script.user_functions().values().filter_map(|f| Some((&f.name, f.decl_span?, FUNCTION)));
+    let variables = script.user_variables().into_iter().filter_map(|(name, v)| Some((name, v.decl_span?, VARIABLE))).collect::<Vec<_>>();
+
+    let mut symbols = functions.map(|(name, span, kind)| (name.clone(), span, kind)).chain(variables).collect::<Vec<_>>();
+
+    symbols.sort_by_key(|(_, span, _)| *span);
+
+    symbols.into_iter().map(|(name, span, kind)| json!({
+        "name": name,
+        "kind": kind,
+        "range": decl_range(span),
+        "selectionRange": decl_range(span)
+    })).collect()
+}
+
+/// The names of the global variables, user functions, and built-ins of the script
+fn completions(script: &Script) -> Vec<Value> {
+    const FUNCTION: u32 = 3; // the LSP's CompletionItemKind
+    const VARIABLE: u32 = 6;
+
+    let variables = script.user_variables().into_keys().map(|name| (name, VARIABLE)).collect::<Vec<_>>();
+    let functions = script.user_functions().keys().chain(script.builtin_names()).map(|name| (name.clone(), FUNCTION));
+
+    let mut names = variables.into_iter().chain(functions).collect::<Vec<_>>();
+
+    names.sort();
+    names.into_iter().map(|(label, kind)| json!({ "label": label, "kind": kind })).collect()
+}
+
+/// The identifier in text under an LSP position, which counts lines and columns from 0
+fn word_at<'a>(text: &'a str, position: &Value) -> Option<&'a str> {
+    let line = text.lines().nth(position["line"].as_u64()? as usize)?;
+    let col = position["character"].as_u64()? as usize;
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    let start = line[..col.min(line.len())].rfind(|c| !is_ident(c)).map_or(0, |i| i + 1);
+    let end = line[start..].find(|c| !is_ident(c)).map_or(line.len(), |i| start + i);
+
+    Some(&line[start..end]).filter(|w| !w.is_empty())
+}
+
+/// An LSP range, from the (line, col) positions of pest, which count from 1
+fn range(line: usize, col: usize, end_line: usize, end_col: usize) -> Value {
+    let pos = |line: usize, col: usize| json!({ "line": line.saturating_sub(1), "character": col.saturating_sub(1) });
+
+    json!({ "start": pos(line, col), "end": pos(end_line, end_col) })
+}
+
+fn decl_range((line, col, len): DeclSpan) -> Value {
+    range(line, col, line, col + len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lsp_server::RequestId;
+    use std::thread;
+
+    const URI: &str = "file:///greet.sss";
+
+    /// Sends a request and returns the result of its response, skipping any notification before it
+    fn request(client: &Connection, id: i32, method: &str, params: Value) -> Value {
+        client.sender.send(Message::Request(Request::new(RequestId::from(id), String::from(method), params))).unwrap();
+
+        loop {
+            match client.receiver.recv().unwrap() {
+                Message::Response(response) => return response.result.unwrap_or(Value::Null),
+                _ => continue
+            }
+        }
+    }
+
+    fn notify(client: &Connection, method: &str, params: Value) {
+        client.sender.send(Message::Notification(Notification::new(String::from(method), params))).unwrap();
+    }
+
+    /// The diagnostics published after a notification
+    fn published(client: &Connection) -> Vec<Value> {
+        match client.receiver.recv().unwrap() {
+            Message::Notification(n) if n.method == "textDocument/publishDiagnostics" => n.params["diagnostics"].as_array().unwrap().clone(),
+            other => panic!("Expected diagnostics, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn serves_diagnostics_symbols_and_definitions() {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || run(&server).unwrap());
+
+        request(&client, 1, "initialize", json!({ "capabilities": {} }));
+        notify(&client, "initialized", json!({}));
+
+        let text = "fun greet(name:str) {\n    var a:str = name;\n}\nvar who:str = \"x\";\ngreet(who);\n";
+
+        notify(&client, "textDocument/didOpen", json!({ "textDocument": { "uri": URI, "languageId": "sss", "version": 1, "text": text } }));
+        assert_eq!(published(&client), Vec::<Value>::new());
+
+        // an error is published, while the symbols are still those of the version that parsed
+        let broken = format!("{}var n:num = missing;\n", text);
+
+        notify(&client, "textDocument/didChange", json!({ "textDocument": { "uri": URI, "version": 2 }, "contentChanges": [{ "text": broken }] }));
+
+        let diagnostics = published(&client);
+
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_eq!(diagnostics[0]["severity"], 1);
+        assert_eq!(diagnostics[0]["range"]["start"], json!({ "line": 5, "character": 12 }));
+
+        let doc = json!({ "textDocument": { "uri": URI } });
+        let symbols = request(&client, 2, "textDocument/documentSymbol", doc.clone());
+        let names = symbols.as_array().unwrap().iter().map(|s| (s["name"].as_str().unwrap(), s["kind"].as_u64().unwrap())).collect::<Vec<_>>();
+
+        assert_eq!(names, vec![("greet", 12), ("who", 13)]);
+
+        // the call to greet on line 4 goes to its declaration
+        let definition = request(&client, 3, "textDocument/definition", json!({ "textDocument": { "uri": URI }, "position": { "line": 4, "character": 2 } }));
+
+        assert_eq!(definition, json!({ "uri": URI, "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 9 } } }));
+
+        let completions = request(&client, 4, "textDocument/completion", doc);
+        let labels = completions.as_array().unwrap().iter().map(|c| c["label"].as_str().unwrap()).collect::<Vec<_>>();
+
+        assert!(labels.contains(&"greet") && labels.contains(&"who") && labels.contains(&"run"), "{:?}", labels);
+
+        request(&client, 5, "shutdown", Value::Null);
+        notify(&client, "exit", Value::Null);
+
+        server.join().unwrap();
+    }
+}
@@ -4,16 +4,39 @@ extern crate pest_derive;
 #[macro_use]
 extern crate log;
 
-use std::fs::File;
-use std::io::Read;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::io::{self, BufRead, Write};
+use std::process;
+use std::time::Instant;
 use pest::Parser;
 use simple_logger;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 
 mod script;
 mod expression;
 mod parse_error;
+mod warning;
+mod diagnostics;
+mod ir_emitter;
+mod source_emitter;
+mod script_builder;
+mod highlight;
+mod render;
+mod library;
+mod exec_string;
+mod units;
+mod builtins;
+#[cfg(feature = "lsp")]
+mod lsp;
 
 use script::Script;
+use parse_error::{ParseError, ErrorKind};
+use expression::Term;
+use render::Renderer;
+use library::Library;
+use script_builder::ScriptBuilder;
 //use program::Term;
 
 
@@ -24,27 +47,435 @@ const _GRAMMAR: &'static str = include_str!("grammar.pest"); // relative to this
 #[grammar = "grammar.pest"] // relative to src
 struct SSSParser;
 
+/// The arguments of the run subcommand, also accepted without a subcommand
+fn run_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("emit").long("emit").takes_value(true).possible_values(&["ir", "source", "highlight"])
+            .help("Prints the lowered code, the source regenerated from it, or the highlight spans instead of running the script"),
+        Arg::with_name("docs").long("docs").conflicts_with("emit")
+            .help("Prints Markdown documentation of the script's functions from their /// comments, instead of running it"),
+        Arg::with_name("precompile").long("precompile").conflicts_with_all(&["eval", "eval-expr"])
+            .help("Writes the functions and variables of the script to a .sssl library next to it, instead of running it"),
+        Arg::with_name("cache").long("cache").conflicts_with_all(&["eval", "eval-expr"])
+            .help("Reads the parsed script from a .sssb file next to it when that is newer than the script, otherwise writes one"),
+        Arg::with_name("define").long("define").takes_value(true).multiple(true).number_of_values(1).value_name("name=value")
+            .help("Substitutes a number or a quoted string for a global variable before the script runs"),
+        Arg::with_name("inline-temps").long("inline-temps")
+            .help("Folds each temp read only by the next statement into that statement before the script runs"),
+        Arg::with_name("entry").long("entry").takes_value(true).value_name("name")
+            .help("Runs the @name block instead of @main or the top-level code"),
+        Arg::with_name("test").long("test").conflicts_with("entry").help("Lists every @test_* block, as ignored until scripts can be run"),
+        Arg::with_name("profile-parse").long("profile-parse")
+            .help("Times parsing the script, instead of running it"),
+        Arg::with_name("profile-build-ast").long("profile-build-ast")
+            .help("Times parsing the script and building it from the parse, instead of running it"),
+        Arg::with_name("eval").long("eval").takes_value(true).value_name("code").conflicts_with("SCRIPT")
+            .help("Runs the code given instead of a script file, errors are reported against <eval>"),
+        Arg::with_name("eval-expr").long("eval-expr").takes_value(true).value_name("expr").conflicts_with_all(&["SCRIPT", "eval"])
+            .help("Prints the str expression given, like --eval with print_args(<expr>);"),
+        Arg::with_name("SCRIPT").help("The script to run, or a .sssl library").index(1),
+        Arg::with_name("ARGS").help("Arguments passed to the script").multiple(true).last(true)
+    ]
+}
+
+fn script_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("SCRIPT").help("The script to read").required(true).index(1)
+}
 
 fn main() {
-    simple_logger::init_with_level(log::Level::Debug).unwrap();
+    let app = App::new("sss")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Simple shell scripting")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(Arg::with_name("verbose").short("v").multiple(true).global(true).help("Logs what the parser is doing, -vv for more detail"))
+        .arg(Arg::with_name("no-warnings").long("no-warnings").global(true).help("Does not print warnings"))
+        .arg(Arg::with_name("diagnostics").long("diagnostics").takes_value(true).global(true)
+            .possible_values(&["human", "json"]).default_value("human").help("How errors and warnings are printed"))
+        .arg(Arg::with_name("color").long("color").takes_value(true).global(true)
+            .possible_values(&["auto", "always", "never"]).default_value("auto").help("When to color errors and warnings"))
+        .args(&run_args())
+        .subcommand(SubCommand::with_name("run").about("Runs a script (the default)").args(&run_args()))
+        .subcommand(SubCommand::with_name("check").about("Reports errors and warnings without running the script").arg(script_arg())
+            .arg(Arg::with_name("check-commands").long("check-commands").help("Warns about commands run from string literals that are not found on the PATH")))
+        .subcommand(SubCommand::with_name("ast").about("Prints the parse tokens and the constructed script").arg(script_arg()))
+        .subcommand(SubCommand::with_name("repl").about("Reads statements from stdin and prints the code they lower to"));
+
+    #[cfg(feature = "lsp")]
+    let app = app.subcommand(SubCommand::with_name("lsp").about("Serves the language server protocol over stdin and stdout"));
+
+    let matches = app.get_matches();
+
+    match matches.subcommand() {
+        ("run", Some(sub)) => run(sub),
+        ("check", Some(sub)) => check(sub),
+        ("ast", Some(sub)) => ast(sub),
+        ("repl", Some(sub)) => repl(sub),
+        #[cfg(feature = "lsp")]
+        ("lsp", Some(_)) => lsp::serve().unwrap_or_else(|e| {
+            eprintln!("Error serving the language server protocol: {}", e);
+            process::exit(1);
+        }),
+        _ => run(&matches)
+    }
+}
+
+/// Starts the logger at the level given by the number of -v flags, nothing is logged without one
+fn init_logging(matches: &ArgMatches) {
+    let level = match matches.occurrences_of("verbose") {
+        0 => return,
+        1 => log::Level::Info,
+        _ => log::Level::Debug
+    };
+
+    simple_logger::init_with_level(level).unwrap();
+}
+
+/// Reads and parses the script, or loads a precompiled library, printing any error and exiting
+fn load(matches: &ArgMatches, path: &str) -> (String, Script) {
+    if path.ends_with(".sssl") {
+        let bytes = fs::read(path).unwrap_or_else(|e| fail(matches, path, ParseError::from_io(path, e)));
+        let lib = Library::from_bytes(path, &bytes).unwrap_or_else(|e| fail(matches, path, e));
+
+        let mut script = ScriptBuilder::new().build().unwrap_or_else(|mut e| fail(matches, path, e.remove(0)));
+
+        script.import_library(&lib).unwrap_or_else(|mut e| fail(matches, path, e.remove(0)));
+
+        return (String::new(), script);
+    }
+
+    let contents = read_source(matches, path);
+    let cache_path = Path::new(path).with_extension("sssb");
+
+    if matches.is_present("cache") {
+        if let Some(script) = read_cache(path, &cache_path) {
+            return (contents, script);
+        }
+    }
+
+    let script = match ScriptBuilder::new().parse_str(&contents).build() {
+        Ok(mut script) => {
+            script.set_file(path);
+            script
+        },
+        Err(errors) => fail_all(matches, path, errors)
+    };
+
+    if matches.is_present("cache") {
+        // the script still runs without a cache, it is just parsed again next time
+        if let Err(e) = script.to_binary().map_err(|e| e.to_string()).and_then(|bytes| fs::write(&cache_path, bytes).map_err(|e| e.to_string())) {
+            warn!("Error writing {}: {}", cache_path.display(), e);
+        }
+    }
+
+    (contents, script)
+}
+
+/// Reads the script parsed from path out of its cache, when the cache is newer than the script and can be read
+fn read_cache(path: &str, cache_path: &Path) -> Option<Script> {
+    let modified = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+
+    match (modified(Path::new(path)), modified(cache_path)) {
+        (Some(source), Some(cache)) if cache > source => { },
+        _ => return None
+    }
+
+    match fs::read(cache_path).map_err(|e| e.to_string()).and_then(|bytes| Script::from_binary(&bytes).map_err(|e| e.to_string())) {
+        Ok(script) => Some(script),
+        Err(e) => {
+            info!("Parsing {} again, as {} cannot be read: {}", path, cache_path.display(), e);
+            None
+        }
+    }
+}
+
+/// The path errors are reported against when the script comes from --eval
+const EVAL_PATH: &str = "<eval>";
+
+/// Returns the code given with --eval or --eval-expr, otherwise reads the script at path, printing any error and exiting
+fn read_source(matches: &ArgMatches, path: &str) -> String {
+    match (matches.value_of("eval"), matches.value_of("eval-expr")) {
+        (Some(code), _) => String::from(code),
+        (_, Some(expr)) => format!("print_args({});", expr),
+        _ => fs::read_to_string(path).unwrap_or_else(|e| fail(matches, path, ParseError::from_io(path, e)))
+    }
+}
+
+fn fail(matches: &ArgMatches, path: &str, error: ParseError) -> ! {
+    fail_all(matches, path, vec![error])
+}
+
+/// Prints each of the errors, in the order given, and exits
+fn fail_all(matches: &ArgMatches, path: &str, errors: Vec<ParseError>) -> ! {
+    for error in errors {
+        if matches.value_of("diagnostics") == Some("json") {
+            println!("{}", error.diagnostic().to_json(path));
+        } else {
+            eprint!("{}", renderer(matches).render(&error.diagnostic()));
+        }
+    }
+
+    process::exit(1);
+}
+
+fn renderer(matches: &ArgMatches) -> Renderer {
+    Renderer::new(matches.value_of("color").unwrap_or("auto"))
+}
+
+fn print_warnings(matches: &ArgMatches, path: &str, script: &Script) {
+    if matches.is_present("no-warnings") {
+        return;
+    }
+
+    let renderer = renderer(matches);
+
+    for w in script.warnings() {
+        if matches.value_of("diagnostics") == Some("json") {
+            println!("{}", w.diagnostic().to_json(path));
+        } else {
+            eprint!("{}", renderer.render(&w.diagnostic()));
+        }
+    }
+}
+
+fn run(matches: &ArgMatches) {
+    init_logging(matches);
+
+    let path = matches.value_of("SCRIPT").or_else(|| matches.value_of("eval").or_else(|| matches.value_of("eval-expr")).map(|_| EVAL_PATH)).unwrap_or_else(|| {
+        eprintln!("{}\n\nFor more information try --help", matches.usage());
+        process::exit(1);
+    });
+
+    if matches.value_of("emit") == Some("highlight") {
+        let contents = read_source(matches, path);
+        let spans = highlight::highlight(&contents).iter().map(|s| s.to_json()).collect::<Vec<_>>();
+
+        println!("[{}]", spans.join(",\n "));
+        return;
+    }
+
+    if matches.is_present("profile-parse") || matches.is_present("profile-build-ast") {
+        profile(matches, path);
+        return;
+    }
+
+    let (_, mut script) = load(matches, path);
+
+    for define in matches.values_of("define").into_iter().flatten() {
+        define_variable(matches, path, &mut script, define);
+    }
+
+    if matches.is_present("inline-temps") {
+        script.inline_single_use_temps();
+    }
 
-    let mut f = File::open("tests/simple.sss").unwrap_or_else(|e| panic!("Error opening file: {}", e));
+    if matches.value_of("emit") == Some("ir") {
+        print!("{}", script.emit_ir());
+        return;
+    }
+
+    if matches.value_of("emit") == Some("source") {
+        print!("{}", script.to_source());
+        return;
+    }
+
+    if matches.is_present("docs") {
+        print!("{}", script.generate_docs());
+        return;
+    }
+
+    if matches.is_present("precompile") {
+        let lib_path = Path::new(path).with_extension("sssl");
+
+        let lib_path = lib_path.to_string_lossy();
+
+        fs::write(lib_path.as_ref(), script.export_to_library().to_bytes()).unwrap_or_else(|e| {
+            fail(matches, path, ParseError::without_location(ErrorKind::Io, &lib_path, format!("Error writing {}: {}", lib_path, e)))
+        });
+        return;
+    }
+
+    print_warnings(matches, path, &script);
+
+    let args = matches.values_of("ARGS").map(|args| args.map(String::from).collect::<Vec<_>>()).unwrap_or_default();
+
+    if matches.is_present("test") {
+        run_tests(matches, &script, &args);
+        return;
+    }
+
+    script.run(matches.value_of("entry"), &args).unwrap_or_else(|e| fail(matches, path, e));
+}
+
+/// Substitutes the value of a --define, like count=3 or name="x", for the variable it names
+fn define_variable(matches: &ArgMatches, path: &str, script: &mut Script, define: &str) {
+    let invalid = |reason: String| -> ! { fail(matches, path, ParseError::without_location(ErrorKind::InvalidDefine, define, reason)) };
+
+    let (name, value) = match define.find('=') {
+        Some(i) => (&define[..i], &define[i + 1..]),
+        None => invalid(format!("Invalid define {}, expected name=value", define))
+    };
+
+    let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        Term::String(String::from(value))
+    } else {
+        match value.parse::<f64>() {
+            Ok(n) if n.is_finite() => Term::Number(n),
+            _ => invalid(format!("Invalid value {} for {}, expected a number or a quoted string", value, name))
+        }
+    };
+
+    script.substitute_variable(name, value).unwrap_or_else(|e| invalid(e.to_string()));
+}
+
+/// The number of times each phase is run when profiling
+const PROFILE_RUNS: usize = 10;
+
+/// Runs f PROFILE_RUNS times, returning the mean and standard deviation of the wall time in milliseconds
+fn time_runs<F: FnMut()>(mut f: F) -> (f64, f64) {
+    let times = (0..PROFILE_RUNS).map(|_| {
+        let start = Instant::now();
+        f();
+        start.elapsed().as_secs_f64() * 1000.0
+    }).collect::<Vec<_>>();
+
+    let mean = times.iter().sum::<f64>() / times.len() as f64;
+    let variance = times.iter().map(|t| (t - mean) * (t - mean)).sum::<f64>() / times.len() as f64;
+
+    (mean, variance.sqrt())
+}
+
+/// Prints how long parsing takes, and with --profile-build-ast how long Script::new takes on the parse,
+/// like "parse: 1.20ms ± 0.10ms, build_ast: 0.50ms ± 0.05ms". The script is never run.
+fn profile(matches: &ArgMatches, path: &str) {
+    let contents = read_source(matches, path);
+
+    // fail on errors up front, so every timed run succeeds
+    let pairs = match SSSParser::parse(Rule::script, &contents) {
+        Ok(mut pairs) => pairs.next().unwrap(),
+        Err(e) => fail(matches, path, ParseError::from_pest_in(&contents, e))
+    };
+
+    let (mean, stddev) = time_runs(|| { SSSParser::parse(Rule::script, &contents).unwrap(); });
+    let mut output = format!("parse: {:.2}ms ± {:.2}ms", mean, stddev);
+
+    if matches.is_present("profile-build-ast") {
+        Script::new(pairs.clone()).unwrap_or_else(|errors| fail_all(matches, path, errors));
+
+        let (mean, stddev) = time_runs(|| { Script::new(pairs.clone()).unwrap(); });
+        output.push_str(&format!(", build_ast: {:.2}ms ± {:.2}ms", mean, stddev));
+    }
+
+    println!("{}", output);
+}
+
+/// Runs each @test_* entry point, exiting with an error if any of them fail. Script::run only checks
+/// the entry point for now, without running its code, so a test that does not fail is ignored rather than passed.
+fn run_tests(matches: &ArgMatches, script: &Script, args: &[String]) {
+    let tests = script.entry_point_names().into_iter().filter(|name| name.starts_with("test_")).collect::<Vec<_>>();
+    let renderer = renderer(matches);
+    let mut failed = 0;
+
+    println!("running {} tests", tests.len());
+
+    // each test runs on its own copy, so one test cannot change what another sees
+    for name in tests.iter() {
+        match script.fresh_clone().run(Some(name), args) {
+            Ok(()) => println!("test @{} ... ignored, not run", name),
+            Err(e) => {
+                println!("test @{} ... FAILED", name);
+                eprint!("{}", renderer.render(&e.diagnostic()));
+                failed += 1;
+            }
+        }
+    }
+
+    println!("\ntest result: {}. 0 passed; {} failed; {} ignored", if failed == 0 { "ok" } else { "FAILED" }, failed, tests.len() - failed);
+
+    if failed > 0 {
+        process::exit(1);
+    }
+}
+
+fn check(matches: &ArgMatches) {
+    init_logging(matches);
+
+    let path = matches.value_of("SCRIPT").unwrap();
+    let (_, mut script) = load(matches, path);
+
+    if matches.is_present("check-commands") {
+        script.check_commands(&env::var("PATH").unwrap_or_default());
+    }
 
-    // read the entire file into memory
-    let mut contents = String::new();
-    f.read_to_string(&mut contents).unwrap_or_else(|e| panic!("Error reading file: {}", e));
+    print_warnings(matches, path, &script);
+}
+
+fn ast(matches: &ArgMatches) {
+    init_logging(matches);
 
-    // parse the file
-    let pairs = SSSParser::parse(Rule::script, &contents).unwrap_or_else(|e| panic!("Error parsing: {}", e)).next().unwrap();
+    let path = matches.value_of("SCRIPT").unwrap();
+    let (contents, script) = load(matches, path);
 
-    for token in pairs.clone().tokens() {
+    for token in SSSParser::parse(Rule::script, &contents).unwrap().tokens() {
         println!("{:?}", token);
     }
 
-    let script = Script::new(pairs);
+    println!("{}", script);
+}
+
+/// Reads statements a line at a time, printing the code each one adds to the session.
+/// A statement is kept only if the script still parses with it, and input that ends
+/// early, like the first line of a function, waits for the following lines.
+fn repl(matches: &ArgMatches) {
+    init_logging(matches);
+
+    let renderer = renderer(matches);
+    let stdin = io::stdin();
+    let mut session = String::new();
+    let mut pending = String::new();
+    let mut prev_ir = String::new();
+
+    prompt("> ");
+
+    for line in stdin.lock().lines() {
+        let line = line.unwrap_or_else(|e| panic!("Error reading stdin: {}", e));
+
+        pending.push_str(&line);
+        pending.push('\n');
 
-    match script {
-        Ok(s) => println!("{}", s),
-        Err(e) => println!("Error: {:?}", e)
+        let source = format!("{}{}", session, pending);
+
+        let pairs = match SSSParser::parse(Rule::script, &source) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(ref e) if e.location == pest::error::InputLocation::Pos(source.len()) => {
+                prompt("... ");
+                continue;
+            },
+            Err(e) => {
+                eprint!("{}", renderer.render(&ParseError::from_pest_in(&source, e).diagnostic()));
+                pending.clear();
+                prompt("> ");
+                continue;
+            }
+        };
+
+        match Script::new(pairs) {
+            Ok(script) => {
+                let ir = script.emit_ir();
+
+                print!("{}", if ir.starts_with(&prev_ir) { &ir[prev_ir.len()..] } else { &ir });
+
+                session = source;
+                prev_ir = ir;
+            },
+            Err(errors) => errors.iter().for_each(|e| eprint!("{}", renderer.render(&e.diagnostic())))
+        }
+
+        pending.clear();
+        prompt("> ");
     }
 }
+
+fn prompt(p: &str) {
+    print!("{}", p);
+    io::stdout().flush().unwrap();
+}
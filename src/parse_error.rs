@@ -1,21 +1,177 @@
-use super::Rule;
+use super::{Rule, SSSParser};
+use pest::Parser;
 use pest::iterators::Pair;
 
 use std::fmt;
+use std::io;
+
+use crate::diagnostics::{Diagnostic, end_of};
+
+/// The kind of a ParseError, each with a short stable code for tooling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Io,
+    Syntax,
+    InvalidNumber,
+    UnexpectedToken,
+    BuiltinRedefinition,
+    FunctionRedefinition,
+    Redeclaration,
+    UndeclaredAssignment,
+    UndefinedVariable,
+    UnknownFunction,
+    UnknownOperator,
+    TypeMismatch,
+    NoReturnValue,
+    MethodOnArray,
+    UnknownMethod,
+    InvalidLibrary,
+    EntryPointRedefinition,
+    UnknownEntryPoint,
+    InvalidCommand,
+    IndexOnNonMap,
+    UntypedMap,
+    UntypedArray,
+    InvalidDefine,
+    NonTrailingDefault,
+    ArgumentCount,
+    MixedArguments,
+    DuplicateArgument,
+    UnknownParameter,
+    InvalidJson,
+    InvalidUnit,
+    InvalidPattern,
+    InvalidConst,
+    ConstAssignment,
+    UnterminatedString,
+    RecursiveDefault
+}
+
+impl ErrorKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Io => "io",
+            ErrorKind::Syntax => "syntax",
+            ErrorKind::InvalidNumber => "invalid-number",
+            ErrorKind::UnexpectedToken => "unexpected-token",
+            ErrorKind::BuiltinRedefinition => "builtin-redefinition",
+            ErrorKind::FunctionRedefinition => "function-redefinition",
+            ErrorKind::Redeclaration => "redeclaration",
+            ErrorKind::UndeclaredAssignment => "undeclared-assignment",
+            ErrorKind::UndefinedVariable => "undefined-variable",
+            ErrorKind::UnknownFunction => "unknown-function",
+            ErrorKind::UnknownOperator => "unknown-operator",
+            ErrorKind::TypeMismatch => "type-mismatch",
+            ErrorKind::NoReturnValue => "no-return-value",
+            ErrorKind::MethodOnArray => "method-on-array",
+            ErrorKind::UnknownMethod => "unknown-method",
+            ErrorKind::InvalidLibrary => "invalid-library",
+            ErrorKind::EntryPointRedefinition => "entry-point-redefinition",
+            ErrorKind::UnknownEntryPoint => "unknown-entry-point",
+            ErrorKind::InvalidCommand => "invalid-command",
+            ErrorKind::IndexOnNonMap => "index-on-non-map",
+            ErrorKind::UntypedMap => "untyped-map",
+            ErrorKind::UntypedArray => "untyped-array",
+            ErrorKind::InvalidDefine => "invalid-define",
+            ErrorKind::NonTrailingDefault => "non-trailing-default",
+            ErrorKind::ArgumentCount => "argument-count",
+            ErrorKind::MixedArguments => "mixed-arguments",
+            ErrorKind::DuplicateArgument => "duplicate-argument",
+            ErrorKind::UnknownParameter => "unknown-parameter",
+            ErrorKind::InvalidJson => "invalid-json",
+            ErrorKind::InvalidUnit => "invalid-unit",
+            ErrorKind::InvalidPattern => "invalid-pattern",
+            ErrorKind::InvalidConst => "invalid-const",
+            ErrorKind::ConstAssignment => "const-assignment",
+            ErrorKind::UnterminatedString => "unterminated-string",
+            ErrorKind::RecursiveDefault => "recursive-default"
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct ParseError {
+    kind: ErrorKind,
     source: String,
     line: usize,
+    col: usize,
+    end_line: usize,
+    end_col: usize,
     reason: String
 }
 
 impl ParseError {
-    pub fn new(rule: Pair<Rule>, reason: String) -> ParseError {
-        let source = String::from(rule.as_str());
-        let line = rule.as_span().start_pos().line_col().0;
+    pub fn new(rule: Pair<Rule>, kind: ErrorKind, reason: String) -> ParseError {
+        let (line, col) = rule.as_span().start_pos().line_col();
+
+        ParseError::from_span(kind, line, col, rule.as_str(), reason)
+    }
+
+    /// Constructs an error for the source text starting at line and col, for when the rule
+    /// it was parsed from is no longer around, like in a check run after parsing
+    pub fn from_span(kind: ErrorKind, line: usize, col: usize, text: &str, reason: String) -> ParseError {
+        let (end_line, end_col) = end_of(line, col, text);
+
+        ParseError { kind, source: String::from(text), line, col, end_line, end_col, reason }
+    }
+
+    /// Converts an error from the pest parser into a ParseError
+    pub fn from_pest(error: pest::error::Error<Rule>) -> ParseError {
+        let ((line, col), (end_line, end_col)) = match error.line_col {
+            pest::error::LineColLocation::Pos(pos) => (pos, pos),
+            pest::error::LineColLocation::Span(start, end) => (start, end)
+        };
+
+        ParseError { kind: ErrorKind::Syntax, source: String::from(error.line()), line, col, end_line, end_col, reason: error.variant.message().to_string() }
+    }
+
+    /// Converts an error from the pest parser parsing the whole source of a script. A """ string that is never
+    /// closed runs to the end of the script, so it is reported where it starts rather than where the script ends.
+    pub fn from_pest_in(source: &str, error: pest::error::Error<Rule>) -> ParseError {
+        let scan = match SSSParser::parse(Rule::unterminated_heredoc_scan, source) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(_) => return ParseError::from_pest(error)
+        };
+
+        match scan.into_inner().find(|p| p.as_rule() == Rule::unterminated_heredoc) {
+            Some(heredoc) => {
+                let (line, col) = heredoc.as_span().start_pos().line_col();
+
+                ParseError::from_span(ErrorKind::UnterminatedString, line, col, "\"\"\"", String::from("A \"\"\" string is never closed"))
+            },
+            None => ParseError::from_pest(error)
+        }
+    }
+
+    /// Constructs an error for a script that could not be read at all
+    pub fn from_io(path: &str, error: io::Error) -> ParseError {
+        ParseError::without_location(ErrorKind::Io, path, format!("Error reading {}: {}", path, error))
+    }
+
+    /// Constructs an error that is not tied to a place in a script's source, reported as line 0
+    pub fn without_location(kind: ErrorKind, source: &str, reason: String) -> ParseError {
+        ParseError { kind, source: String::from(source), line: 0, col: 0, end_line: 0, end_col: 0, reason }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
 
-        ParseError { source, line, reason}
+    pub fn diagnostic(&self) -> Diagnostic<'_> {
+        Diagnostic {
+            line: self.line,
+            col: self.col,
+            end_line: self.end_line,
+            end_col: self.end_col,
+            severity: "error",
+            code: self.kind.code(),
+            source: &self.source,
+            message: &self.reason
+        }
     }
 }
 
@@ -26,3 +182,30 @@ impl fmt::Display for ParseError {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_and_from_span_agree() {
+        let source = "var a:num = 1;\nvar b:str =\n    \"x\";\n";
+        let script = SSSParser::parse(Rule::script, source).unwrap().next().unwrap();
+        let lines = script.into_inner().filter(|p| p.as_rule() == Rule::program_line).collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2);
+
+        for pair in lines {
+            let (line, col) = pair.as_span().start_pos().line_col();
+            let from_span = ParseError::from_span(ErrorKind::TypeMismatch, line, col, pair.as_str(), String::from("reason"));
+            let new = ParseError::new(pair, ErrorKind::TypeMismatch, String::from("reason"));
+
+            assert_eq!(new.diagnostic().to_json("f.sss"), from_span.diagnostic().to_json("f.sss"));
+            assert_eq!(new.to_string(), from_span.to_string());
+        }
+
+        // the span of the second statement ends on the line after it starts
+        let second = ParseError::from_span(ErrorKind::TypeMismatch, 2, 1, "var b:str =\n    \"x\";", String::from("reason"));
+
+        assert_eq!((second.end_line, second.end_col), (3, 9));
+    }
+}
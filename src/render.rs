@@ -0,0 +1,45 @@
+use crate::diagnostics::Diagnostic;
+
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Renders errors and warnings for people, in color when writing to a terminal.
+/// Without color the output is exactly the Display format of ParseError and Warning.
+pub struct Renderer {
+    color: bool
+}
+
+impl Renderer {
+    /// Decides on color from a --color value of always, never, or auto.
+    /// With auto, color is used when stderr is a terminal and NO_COLOR is unset.
+    pub fn new(choice: &str) -> Renderer {
+        let color = match choice {
+            "always" => true,
+            "never" => false,
+            _ => atty::is(atty::Stream::Stderr) && std::env::var_os("NO_COLOR").is_none()
+        };
+
+        Renderer { color }
+    }
+
+    pub fn render(&self, diag: &Diagnostic) -> String {
+        let label = if diag.severity == "warning" { "warning: " } else { "" };
+
+        if !self.color {
+            return format!("{}: {}\n{}{}\n", diag.line, diag.source, label, diag.message);
+        }
+
+        let location = format!("{}: ", diag.line);
+        let highlight = if diag.severity == "warning" { YELLOW } else { RED };
+
+        // underline the first line of the source, multi-line sources are functions and blocks
+        let width = diag.source.lines().next().map_or(0, |l| l.chars().count()).max(1);
+        let carets = format!("{}{}{}{}", " ".repeat(location.len()), highlight, "^".repeat(width), RESET);
+
+        let label = if label.is_empty() { String::new() } else { format!("{}{}{}", highlight, label, RESET) };
+
+        format!("{}{}{}{}\n{}\n{}{}\n", BOLD, location, RESET, diag.source, carets, label, diag.message)
+    }
+}
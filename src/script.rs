@@ -1,30 +1,217 @@
-use super::Rule;
-use pest::iterators::Pair;
+use super::{Rule, SSSParser};
+use pest::Parser;
+use pest::iterators::{Pair, Pairs};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 
-use crate::parse_error::ParseError;
+use serde::{Serialize, Deserialize};
+
+use crate::parse_error::{ParseError, ErrorKind};
+use crate::warning::{Warning, WarningKind};
+use crate::ir_emitter::IrEmitter;
+use crate::source_emitter::SourceEmitter;
+use crate::library::Library;
+use crate::exec_string;
+use crate::units;
+use crate::builtins::BuiltinRegistry;
 
 use crate::expression::*;
 
 // helpful type alias
 pub type SymbolTable = HashMap<String, Variable>;
-type FunctionTable = HashMap<String, Function>;
+pub type FunctionTable = HashMap<String, Function>;
+
+/// Returns the literal when an expression is nothing more than a string or a number
+fn literal_term(expression: &Pair<Rule>) -> Result<Option<Term>, ParseError> {
+    let parts = expression.clone().into_inner().flatten().filter(|p| !matches!(p.as_rule(), Rule::primary | Rule::term | Rule::integer | Rule::float)).collect::<Vec<_>>();
+
+    match parts.as_slice() {
+        [number] if number.as_rule() == Rule::number => Ok(Some(Term::Number(parse_number(number.clone())?))),
+        [string] if string.as_rule() == Rule::string => Ok(Some(Term::String(String::from(string.as_str())))),
+        [c] if c.as_rule() == Rule::char_literal => Ok(Some(Term::from_char_literal(c.as_str()))),
+        [heredoc] if heredoc.as_rule() == Rule::heredoc => Ok(Some(Term::from_heredoc(heredoc.as_str()))),
+        _ => Ok(None)
+    }
+}
+
+/// Returns the names of the variables holding functions, out of vars and those assigned in code
+fn fun_variables<'a>(vars: impl Iterator<Item = &'a Variable>, code: &'a [Expression]) -> HashSet<String> {
+    let assigned = code.iter().flat_map(|e| e.flatten()).filter_map(|e| match e {
+        Expression::Assignment(_, a) => Some(&a.lhs),
+        _ => None
+    });
+
+    vars.chain(assigned).filter(|v| matches!(v.var_def.var_type, VarType::Fun(..))).map(|v| v.name.clone()).collect()
+}
+
+/// Parses a number rule into an f64, returning an error instead of panicking
+/// if the value cannot be represented
+fn parse_number(number: Pair<Rule>) -> Result<f64, ParseError> {
+    match number.as_str().parse::<f64>() {
+        Ok(n) if n.is_finite() => Ok(n),
+        Ok(_) => Err(ParseError::new(number.clone(), ErrorKind::InvalidNumber, format!("Number out of range: {}", number.as_str()))),
+        Err(e) => Err(ParseError::new(number.clone(), ErrorKind::InvalidNumber, format!("Invalid number {}: {}", number.as_str(), e)))
+    }
+}
+
+/// Returns the next pair, or a ParseError against the parent if the parse tree ended early
+fn next_pair<'i>(pairs: &mut Pairs<'i, Rule>, parent: &Pair<'i, Rule>) -> Result<Pair<'i, Rule>, ParseError> {
+    pairs.next().ok_or_else(|| ParseError::new(parent.clone(), ErrorKind::UnexpectedToken, format!("Incomplete {:?}: {}", parent.as_rule(), parent.as_str())))
+}
+
+/// Describes where a name was first declared, for errors about declaring it again
+fn previously(what: &str, decl_span: &Option<DeclSpan>) -> String {
+    match decl_span {
+        Some((line, _, _)) => format!(", previously {} at line {}", what, line),
+        None => String::new()
+    }
+}
+
+/// The type of the values when var_def is a map, and not an array of them
+fn map_values(var_def: &VarDef) -> Option<VarType> {
+    if var_def.is_array {
+        return None;
+    }
+
+    var_def.var_type.map_value().cloned()
+}
+
+/// Inlines temps that are assigned and then read exactly once by the very next expression,
+/// returning the names of the temps that were removed
+fn inline_temps(code: &mut Vec<Expression>) -> Vec<String> {
+    let mut use_counts = HashMap::<String, usize>::new();
+    let mut removed = Vec::new();
+
+    // a temp assigned in a branch of a conditional is only read in that branch, or is the result read after it
+    for e in code.iter_mut() {
+        if let Expression::Conditional(_, c) = e {
+            removed.extend(inline_temps(&mut c.then_code));
+            removed.extend(inline_temps(&mut c.else_code));
+        }
+    }
+
+    for var in code.iter().flat_map(|e| e.operands()) {
+        *use_counts.entry(var.name.clone()).or_insert(0) += 1;
+    }
+
+    let mut i = 0;
+
+    while i + 1 < code.len() {
+        let temp = match &code[i] {
+            Expression::Assignment(_, a) if a.lhs.is_temp() && use_counts.get(&a.lhs.name) == Some(&1) => {
+                Some((a.lhs.clone(), a.rhs.clone()))
+            },
+            _ => None
+        };
+
+        match temp {
+            Some((lhs, rhs)) if code[i + 1].inline(&lhs, &rhs) => {
+                code.remove(i);
+                removed.push(lhs.name);
+            },
+            _ => i += 1
+        }
+    }
+
+    removed
+}
+
+/// Why a variable could not be substituted with a value, see Script::substitute_variable()
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubstError {
+    NotFound(String),
+    TypeMismatch(String, VarDef, VarDef), // the variable, its type, and the type of the value
+    NotConstant(String)                   // the variable is assigned again after it is declared
+}
+
+impl fmt::Display for SubstError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubstError::NotFound(name) => write!(f, "Unknown variable {}", name),
+            SubstError::TypeMismatch(name, var_def, value) => write!(f, "Cannot substitute a {} for {}, a {}", value, name, var_def),
+            SubstError::NotConstant(name) => write!(f, "Cannot substitute a value for {}, it is assigned more than once", name)
+        }
+    }
+}
+
+/// Why code could not be refactored, see Script::extract_function() and Script::inline_function()
+#[cfg(test)]
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefactorError {
+    EmptyRange(usize, usize),     // no code was generated from the lines
+    AmbiguousSpan(usize),         // a statement at the line is partly inside the range and partly outside it
+    MultipleOutputs(Vec<String>), // more than one variable assigned in the range is read after it
+    NameTaken(String),            // the new function's name is already a function or a variable
+    NotACall(usize, String),      // the expression at the index is not a call to the function
+    UnknownFunction(String),      // there is no user function with the name
+    VariadicFunction(String),     // the function takes a variadic param, which has no single argument to substitute
+    NoValue(String)               // the function returns a value, but assigns nothing that could be it
+}
+
+#[cfg(test)]
+impl fmt::Display for RefactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RefactorError::EmptyRange(start, end) => write!(f, "No code on lines {} to {}", start, end),
+            RefactorError::AmbiguousSpan(line) => write!(f, "The statement at line {} is only partly in the lines", line),
+            RefactorError::MultipleOutputs(names) => write!(f, "Only one value can be returned, but {} are all read afterwards", names.join(", ")),
+            RefactorError::NameTaken(name) => write!(f, "{} is already defined", name),
+            RefactorError::NotACall(index, name) => write!(f, "Expression {} is not a call to {}", index, name),
+            RefactorError::UnknownFunction(name) => write!(f, "Unknown function {}", name),
+            RefactorError::VariadicFunction(name) => write!(f, "{} takes a variadic parameter, so it cannot be inlined", name),
+            RefactorError::NoValue(name) => write!(f, "{} assigns nothing, so it has no value to inline", name)
+        }
+    }
+}
+
+/// Why a script could not be read from the bytes written by Script::to_binary()
+#[derive(Debug)]
+pub enum BinaryError {
+    Bincode(bincode::Error),
+    VersionMismatch(u32, u32) // the format version of the bytes, and the one this build reads
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryError::Bincode(e) => write!(f, "{}", e),
+            BinaryError::VersionMismatch(found, expected) => write!(f, "Written in format version {}, but only version {} can be read", found, expected)
+        }
+    }
+}
+
+impl From<bincode::Error> for BinaryError {
+    fn from(e: bincode::Error) -> BinaryError {
+        BinaryError::Bincode(e)
+    }
+}
+
+/// The version of the format written by Script::to_binary(), which must change whenever a type saved in it changes
+const FORMAT_VERSION: u32 = 2;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Script {
-    user_functions: FunctionTable,     // the functions defined in this script + built-ins
-    builtin_functions: FunctionTable,  // built-in functions
+    user_functions: FunctionTable,     // the functions defined in this script
+    #[serde(skip, default = "BuiltinRegistry::standard")]
+    builtins: BuiltinRegistry,         // built-in functions, which are not saved, so a script read back has the standard ones
     variables: SymbolTable,            // variables and their current values
     code: Vec<Expression>,             // list of code to execute in order
+    entry_points: HashMap<String, Vec<Expression>>, // code of the @name blocks, run instead of the top-level code
+    warnings: Vec<Warning>,            // non-fatal issues found while parsing
+    empty_map: Option<VarType>,        // the type of the values of an empty map literal, from the map it is assigned to
+    empty_array: Option<VarType>,      // the type of the elements of an empty array literal, from the array it is assigned to
+    commands: Vec<(String, Location)>, // the program of each exec string written as a literal, and the call it is passed to
+    #[serde(skip)]
+    expanding: Vec<String>,            // the functions whose default values are being lowered, innermost last
     tmp_num: usize
 }
 
 impl fmt::Display for Script {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         writeln!(f, "FUNCTIONS: {:?}", self.user_functions)?;
-        writeln!(f, "VARIABLES: {:?}", self.variables)?;
+        writeln!(f, "VARIABLES: {:?}", self.user_variables())?;
+        writeln!(f, "TEMPS: {}", self.temp_variable_count())?;
 
         for e in &self.code {
             writeln!(f, "{}", e)?;
@@ -37,12 +224,10 @@ impl fmt::Display for Script {
 impl Script {
     /// Generates a temp variable with the same type as the variable passed
     fn generate_temp(&mut self, var_def: &VarDef) -> Variable {
-        let var_name = unsafe {
-            self.tmp_num += 1;
-            String::from(format!("_t{}", self.tmp_num))
-        };
+        self.tmp_num += 1;
+        let var_name = format!("_t{}", self.tmp_num);
 
-        let ret = Variable{ name:var_name.clone(), var_def: var_def.clone() };
+        let ret = Variable::from_def(&var_name, var_def.clone());
 
         self.variables.insert(var_name, ret.clone());
 
@@ -50,49 +235,71 @@ impl Script {
     }
 
     /// Constructs a Script object from a set of rules return from the parser
-    pub fn new(pairs: Pair<Rule>) -> Result<Script, ParseError> {
+    pub fn new(pairs: Pair<Rule>) -> Result<Script, Vec<ParseError>> {
+        Script::with_builtins(pairs, BuiltinRegistry::standard())
+    }
+
+    /// Constructs a Script object that can call the given built-in functions, instead of the standard set.
+    /// Parsing stops at the first error, but once it is done every call that is wrong is reported.
+    pub fn with_builtins(pairs: Pair<Rule>, builtins: BuiltinRegistry) -> Result<Script, Vec<ParseError>> {
+        let mut script = Script::parse(pairs.clone(), builtins).map_err(|e| vec![e])?;
+
+        // the calls are checked again now that every function is known
+        let errors = script.validate_all_function_calls();
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        // finally, warn about any parameters that hide a global variable
+        for inner in pairs.into_inner().filter(|p| p.as_rule() == Rule::fun) {
+            script.check_shadowed_params(inner);
+        }
+
+        Ok(script)
+    }
+
+    /// Builds the functions, top-level code, and entry points of the script from the parse
+    fn parse(pairs: Pair<Rule>, builtins: BuiltinRegistry) -> Result<Script, ParseError> {
         let mut script = Script {
             user_functions: FunctionTable::new(),
-            builtin_functions: FunctionTable::new(),
+            builtins,
             variables: SymbolTable::new(),
             code: Vec::new(),
+            entry_points: HashMap::new(),
+            warnings: Vec::new(),
+            empty_map: None,
+            empty_array: None,
+            commands: Vec::new(),
+            expanding: Vec::new(),
             tmp_num: 0
         };
 
-
-        // construct all of our built-in functions
-        let run_fun = Function::new("run", vec![
-                Variable{name: String::from("input"), var_def: VarDef{var_type:VarType::Pipe, is_array:false}},
-                Variable{name: String::from("exec"), var_def: VarDef{var_type:VarType::String, is_array:false}}
-            ], Some(VarDef{var_type:VarType::Pipe, is_array:true}));
-
-        script.builtin_functions.insert(String::from("run"), run_fun);
-
         let inner = pairs.clone().into_inner();
 
         // loop through all functions first, to build up functions hash map
         for inner in inner {
             match inner.as_rule() {
-                Rule::program_line => { continue },
+                Rule::program_line | Rule::labeled_block => { continue },
                 Rule::fun => {
                     let fun = script.process_fun(inner.clone())?;
 
                     let fun_name = fun.clone().name;
 
-                    if script.builtin_functions.contains_key(&fun_name) {
-                        return Err(ParseError::new(inner, format!("Re-definition of built-in function: {:?}", fun)));
+                    if let Some(builtin) = script.builtins.get(&fun_name) {
+                        return Err(ParseError::new(inner, ErrorKind::BuiltinRedefinition, format!("Re-definition of built-in function: {}", builtin.display_header())));
                     }
 
-                    if script.user_functions.insert(fun_name, fun.clone()).is_some() {
-                        return Err(ParseError::new(inner, format!("Function re-definition: {:?}", fun)));
+                    if let Some(prev) = script.user_functions.insert(fun_name, fun.clone()) {
+                        return Err(ParseError::new(inner, ErrorKind::FunctionRedefinition, format!("Function re-definition of {}{}", prev.display_header(), previously("defined", &prev.decl_span))));
                     }
                 },
                 Rule::EOI => { }
-                _ => { println!("UNKNOWN: {:?}", inner) }
+                _ => { return Err(ParseError::new(inner.clone(), ErrorKind::UnexpectedToken, format!("Unexpected token: {:?}", inner.as_rule()))) }
             }
         }
 
-        let inner = pairs.into_inner();
+        let inner = pairs.clone().into_inner();
 
         // now go through all the program lines
         for inner in inner {
@@ -100,387 +307,2199 @@ impl Script {
                 Rule::program_line => {
                     script.process_program_line(inner)?
                 },
-                Rule::fun => { continue },
+                Rule::fun | Rule::labeled_block => { continue },
                 Rule::EOI => { break }
-                _ => { panic!("UNKNOWN: {:?}", inner) }
+                _ => { return Err(ParseError::new(inner.clone(), ErrorKind::UnexpectedToken, format!("Unexpected token: {:?}", inner.as_rule()))) }
             };
         }
 
+        // entry points come after the top-level code, so they can use its variables
+        for inner in pairs.clone().into_inner().filter(|p| p.as_rule() == Rule::labeled_block) {
+            let (name, code) = script.process_labeled_block(inner.clone())?;
+
+            if script.entry_points.insert(name.clone(), code).is_some() {
+                return Err(ParseError::new(inner, ErrorKind::EntryPointRedefinition, format!("Entry point re-definition of @{}", name)));
+            }
+        }
+
         Ok(script)
     }
 
-    pub fn run(&self) {
+    /// Adds a warning for each command run from a literal exec string whose program is not found
+    /// on path, a list of directories separated by colons. Nothing is run.
+    pub fn check_commands(&mut self, path: &str) {
+        for (program, loc) in &self.commands {
+            if exec_string::which(program, path).is_none() {
+                self.warnings.push(Warning::from_location(loc, WarningKind::MissingCommand, format!("Command {} is not found on the PATH", program)));
+            }
+        }
+    }
+
+    /// Returns the warnings generated while constructing the script
+    pub fn warnings(&self) -> &Vec<Warning> {
+        &self.warnings
+    }
 
+    /// Returns the user function whose code contains the given line, or None for top-level code
+    #[cfg(test)]
+    pub fn find_function_by_line(&self, line: usize) -> Option<&Function> {
+        self.user_functions.values().find(|fun| {
+            fun.code.iter().any(|e| e.location().contains_line(line))
+        })
     }
 
-    fn process_fun(&mut self, fun: Pair<Rule>) -> Result<Function, ParseError> {
-        let fun_str = String::from(fun.as_str());
-        let mut inner = fun.clone().into_inner();
+    /// Returns the first expression, in the top-level code, a function, or an entry point, generated from the given line
+    #[cfg(test)]
+    pub fn find_expression_at_line(&self, line: usize) -> Option<&Expression> {
+        self.all_expressions().find(|e| e.location().contains_line(line))
+    }
 
-        let mut signature = inner.next().unwrap().into_inner();
+    /// Every expression in the script: the top-level code, then the code of each function and entry point.
+    /// The expressions in the branches of a conditional come right after it.
+    pub fn all_expressions(&self) -> impl Iterator<Item = &Expression> {
+        let fun_code = self.user_functions.values().flat_map(|fun| fun.code.iter());
+        let entry_code = self.entry_points.values().flat_map(|code| code.iter());
 
-        let fun_name = String::from(signature.next().unwrap().as_str());
+        self.code.iter().chain(fun_code).chain(entry_code).flat_map(|e| e.flatten())
+    }
 
-        let mut next = signature.next();
-        let mut fun_vars = SymbolTable::new();
-        let mut ret_val = Option::None;
+    /// Every expression in the script, in the same order as all_expressions(), but without
+    /// those in the branches of conditionals, which are reached through the conditional
+    pub fn all_expressions_mut(&mut self) -> impl Iterator<Item = &mut Expression> {
+        let fun_code = self.user_functions.values_mut().flat_map(|fun| fun.code.iter_mut());
+        let entry_code = self.entry_points.values_mut().flat_map(|code| code.iter_mut());
 
-        while next.is_some() {
-            match next.clone().unwrap().as_rule() {
-                Rule::param_list => {
-                    let param_list = next.unwrap().into_inner().map(|dec| {
-                        Variable::new(dec)
-                    }).collect::<Vec<_>>();
+        self.code.iter_mut().chain(fun_code).chain(entry_code)
+    }
 
-                    // insert them all into the function's symbol table
-                    param_list.iter().for_each(|v| {
-                        fun_vars.insert(v.clone().name, v.clone());
-                    });
+    /// Every assignment in the script, including the ones to temps
+    pub fn all_assignments(&self) -> impl Iterator<Item = &Assignment> {
+        self.all_expressions().filter_map(|e| match e {
+            Expression::Assignment(_, a) => Some(a),
+            _ => None
+        })
+    }
 
-                },
-                Rule::var_def => {
-                    ret_val = Some(VarDef::new(next.unwrap()));
-                },
-                _ => { return Err(ParseError::new(fun, format!("Unexpected token: {:?}", next.unwrap()))) }
+    /// Every function call in the script, both statements and the right-hand-side of assignments
+    #[cfg(test)]
+    pub fn all_function_calls(&self) -> impl Iterator<Item = &FunctionCall> {
+        self.all_expressions().filter_map(|e| match e {
+            Expression::FunctionCall(_, fc) => Some(fc),
+            Expression::Assignment(_, Assignment { rhs: RightHandSide::FunctionCall(fc), .. }) => Some(fc),
+            _ => None
+        })
+    }
+
+    /// Checks every call in the script against the function it calls, now that all of them are known:
+    /// the function exists, it is given the right number of arguments, and each argument has the type
+    /// of its param. Unlike parsing, this does not stop at the first problem, and returns an error for each.
+    pub fn validate_all_function_calls(&self) -> Vec<ParseError> {
+        // each block of code, with the variables holding functions that it can call
+        let mut blocks = vec![(&self.code, fun_variables(self.variables.values(), &self.code))];
+
+        blocks.extend(self.entry_points.values().map(|code| (code, fun_variables(self.variables.values(), code))));
+        blocks.extend(self.user_functions.values().map(|fun| (&fun.code, fun_variables(fun.params.iter(), &fun.code))));
+
+        let mut errors = Vec::new();
+
+        for (code, fun_vars) in blocks {
+            for (loc, call) in code.iter().flat_map(|e| e.flatten()).filter_map(|e| match e {
+                Expression::FunctionCall(loc, fc) => Some((loc, fc)),
+                Expression::Assignment(loc, Assignment { rhs: RightHandSide::FunctionCall(fc), .. }) => Some((loc, fc)),
+                _ => None
+            }) {
+                if let Err(error) = self.validate_function_call(loc, call, &fun_vars) {
+                    errors.push(error);
+                }
             }
+        }
+
+        errors.sort_by_key(|e| { let d = e.diagnostic(); (d.line, d.col) });
+        errors
+    }
+
+    /// Checks one call for validate_all_function_calls, where fun_vars are the variables holding
+    /// functions in the calling code. A call through one of them is checked against its type.
+    fn validate_function_call(&self, loc: &Location, call: &FunctionCall, fun_vars: &HashSet<String>) -> Result<(), ParseError> {
+        let error = |kind, reason| ParseError::from_span(kind, loc.line, loc.col, &loc.text, reason);
+        let name = &call.fun.name;
+
+        let (fun, is_builtin) = if fun_vars.contains(name) {
+            (&call.fun, false)
+        } else if let Some(fun) = self.user_functions.get(name) {
+            (fun, false)
+        } else if let Some(fun) = self.builtins.get(name) {
+            (fun, true)
+        } else {
+            return Err(error(ErrorKind::UnknownFunction, format!("Unknown function {}", name)));
+        };
 
-            next = signature.next();
+        // a leading pipe param can be left out, as run("ls") leaves out the input pipe
+        let args = &call.var_list;
+        let fixed = fun.params.len() - usize::from(fun.is_variadic);
+        let too_few = if fun.is_variadic { !matches!(args.first(), Some(a) if a.var_def.var_type == VarType::Pipe) } else { args.len() < fixed };
+        let skips_pipe = too_few && matches!(fun.params.first(), Some(p) if p.var_def == VarDef::from_type(&VarType::Pipe));
+        let params = &fun.params[usize::from(skips_pipe)..fixed];
+
+        if args.len() < params.len() || (!fun.is_variadic && args.len() > params.len()) {
+            let at_least = if fun.is_variadic { "at least " } else { "" };
+
+            return Err(error(ErrorKind::ArgumentCount, format!("{} takes {}{} arguments, but {} were given", name, at_least, params.len(), args.len())));
         }
 
-        let block = inner.next().unwrap().into_inner();
+        // a builtin's map param takes a map with any type of values
+        let accepts = |param: &VarDef, arg: &VarDef| {
+            arg.is_compatible_with(param) || (is_builtin && param.is_array == arg.is_array && param.var_type.map_value().is_some() && arg.var_type.map_value().is_some())
+        };
+
+        for (i, (param, arg)) in params.iter().zip(args).enumerate() {
+            let broadcast = i == 0 && call.broadcast && arg.var_def == VarDef::from_array(&VarType::Pipe);
 
-        for pl in block {
-            self.process_program_line(pl);
+            if !broadcast && !accepts(&param.var_def, &arg.var_def) {
+                return Err(error(ErrorKind::TypeMismatch, format!("Cannot pass a {} as {} of {}, it must be a {}", arg.var_def, param.name, name, param.var_def)));
+            }
         }
 
-        // this is a bit of a hack, process_program_lines is going to fill self.code
-        // but we need these code attached to a function, not the "main" code
-        // so we copy that Vec, and drain the other
-        let mut fun_code = Vec::with_capacity(self.code.len());
+        if let (true, Some(rest)) = (fun.is_variadic, fun.params.last()) {
+            let element = VarDef::from_type(&rest.var_def.var_type);
 
-        fun_code.extend(self.code.drain(1..));
+            if let Some(arg) = args[params.len()..].iter().find(|arg| !accepts(&element, &arg.var_def)) {
+                return Err(error(ErrorKind::TypeMismatch, format!("Cannot pass a {} as part of {} of {}, it must be a {}", arg.var_def, rest.name, name, element)));
+            }
+        }
 
-        Ok( Function {
-            name: fun_name,
-            params: fun_vars,
-            ret_type: ret_val,
-            code: fun_code
-        } )
+        Ok( () )
     }
 
-    fn process_program_line(&mut self, program_line: Pair<Rule>) -> Result<(), ParseError> {
-        let program_line = program_line.into_inner().next().unwrap();
-        let pl_str = String::from(program_line.as_str());
+    /// Returns the global variable with the given name, temps included
+    ///
+    /// ```ignore
+    /// if script.has_variable("count") {
+    ///     script.substitute_variable("count", Term::Number(3.0))?;
+    /// }
+    /// ```
+    #[cfg(test)]
+    pub fn get_variable(&self, name: &str) -> Option<&Variable> {
+        self.variables.get(name)
+    }
 
-        match program_line.as_rule() {
-            Rule::declaration => {
-                // var_def, expression
-                let mut inner = program_line.clone().into_inner();
+    /// Returns the user function, or failing that the built-in, with the given name
+    pub fn get_function(&self, name: &str) -> Option<&Function> {
+        self.user_functions.get(name).or_else(|| self.builtins.get(name))
+    }
 
-                let var_def = inner.next().unwrap();
-                let lhs = Variable::new(var_def);
+    /// The global variables declared in the script, leaving out the temps generated for expressions
+    pub fn user_variables(&self) -> SymbolTable {
+        self.variables.iter().filter(|(_, v)| !v.is_temp()).map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
 
-                if self.variables.insert(lhs.name.clone(), lhs.clone()).is_some() {
-                    return Err(ParseError::new(program_line, format!("Redeclaration of {}", lhs.name)));
-                }
+    /// The functions defined in the script, leaving out the built-ins
+    #[cfg(feature = "lsp")]
+    pub fn user_functions(&self) -> &FunctionTable {
+        &self.user_functions
+    }
 
-                debug!("Declared variable: {:?}", lhs);
+    /// The names of the built-ins the script can call, sorted
+    #[cfg(feature = "lsp")]
+    pub fn builtin_names(&self) -> Vec<&String> {
+        self.builtins.names()
+    }
 
-                // process the expression on the right-hand-side
-                let rhs = self.process_expression(inner.next().unwrap())?;
+    /// The number of temps assigned in the code, the top-level code, functions, and entry points alike,
+    /// which goes down as optimizations like inline_single_use_temps() remove them
+    pub fn temp_variable_count(&self) -> usize {
+        self.all_assignments().filter(|a| a.lhs.is_temp()).map(|a| &a.lhs.name).collect::<HashSet<_>>().len()
+    }
 
-                self.code.push(Expression::Assignment(pl_str, Assignment{ lhs, rhs }));
-            },
-            Rule::assignment => {
-                // identifier, expression
-                let mut inner = program_line.clone().into_inner();
+    #[cfg(test)]
+    pub fn has_variable(&self, name: &str) -> bool {
+        self.variables.contains_key(name)
+    }
 
-                let ident = inner.next().unwrap().as_str().trim();
+    #[cfg(test)]
+    pub fn has_function(&self, name: &str) -> bool {
+        self.get_function(name).is_some()
+    }
 
-                // check to make sure we've previously declared this variable
-                if !self.variables.contains_key(ident) {
-                    return Err(ParseError::new(program_line, format!("Assignment to undeclared variable: {}", ident)));
-                }
+    /// Returns where the global variable or user function with the given name was declared,
+    /// preferring the variable when both exist
+    #[cfg(any(test, feature = "lsp"))]
+    pub fn find_declaration(&self, name: &str) -> Option<DeclSpan> {
+        let var_span = self.variables.get(name).and_then(|v| v.decl_span);
 
-                let var = self.variables.get(ident).unwrap().clone();
+        var_span.or_else(|| self.user_functions.get(name).and_then(|f| f.decl_span))
+    }
 
-                let rhs = self.process_expression(inner.next().unwrap())?;
+    /// Writes the whole script, after the version of the format, so it can be read back without parsing it again
+    pub fn to_binary(&self) -> Result<Vec<u8>, BinaryError> {
+        Ok(bincode::serialize(&(FORMAT_VERSION, self))?)
+    }
 
-                self.code.push(Expression::Assignment(pl_str, Assignment {lhs:var.clone(), rhs}));
-            },
-            Rule::method_call => {
-                let pl_str = String::from(program_line.as_str());
-                let fun_call = self.process_method_call(program_line)?;
+    /// Reads a script written by to_binary(), with the standard built-ins
+    pub fn from_binary(data: &[u8]) -> Result<Script, BinaryError> {
+        let version = bincode::deserialize::<u32>(data)?;
 
-                self.code.push(Expression::FunctionCall(pl_str, fun_call));
-            },
-            Rule::fun_call => {
-                let pl_str = String::from(program_line.as_str());
-                let fun_call = self.process_fun_call(program_line)?;
+        if version != FORMAT_VERSION {
+            return Err(BinaryError::VersionMismatch(version, FORMAT_VERSION));
+        }
 
-                self.code.push(Expression::FunctionCall(pl_str, fun_call));
-            },
-            _ => {
-                return Err(ParseError::new(program_line, String::from("Unknown program line")));
-            }
-        };
+        let (_, script) = bincode::deserialize::<(u32, Script)>(data)?;
 
-        Ok( () )
+        Ok(script)
     }
 
-    fn process_expression(&mut self, expression: Pair<Rule>) -> Result<RightHandSide, ParseError> {
-        let exp_str = String::from(expression.as_str());
-        let mut inner = expression.clone().into_inner();
-
-        let op1 = self.process_primary(inner.next().unwrap())?;
+    /// Records the file the script was read from in the location of every expression
+    pub fn set_file(&mut self, file: &str) {
+        for loc in self.all_expressions_mut().flat_map(|e| e.locations_mut()) {
+            loc.file = String::from(file);
+        }
+    }
 
-        let mut rhs = if inner.peek().is_some() {
-            let op_rule = inner.next().unwrap();
-            let op = match op_rule.as_str() {
-                "+" => Operator::Add,
-                "-" => Operator::Sub,
-                "*" => Operator::Mul,
-                "/" => Operator::Div,
-                _ => return Err(ParseError::new(expression, format!("Unknown operator {}", op_rule.as_str())))
+    /// Copies the user functions and global variables, leaving out temps, so they can be saved and imported elsewhere.
+    /// The code kept is what assigns the variables, and the temps it reads, so calls made for their effect are left out.
+    pub fn export_to_library(&self) -> Library {
+        let mut needed = HashSet::new();
+        let mut code = Vec::new();
+
+        for e in self.code.iter().rev() {
+            let assigns = match e {
+                Expression::Assignment(_, a) => !a.lhs.is_temp() || needed.contains(&a.lhs.name),
+                Expression::IndexAssignment(..) => true,
+                Expression::FunctionCall(..) => false,
+                Expression::Conditional(_, c) => needed.contains(&c.result.name)
             };
 
-            let op2 = self.process_primary(inner.next().unwrap())?;
-
-            if op1.var_def != op2.var_def {
-                return Err(ParseError::new(expression, format!("Attempting to combine values of different types {:?} != {:?}", op1.var_def, op2.var_def)))
+            if assigns {
+                needed.extend(e.operands().into_iter().map(|v| v.name.clone()));
+                code.push(e.clone());
             }
+        }
 
-            RightHandSide::Operation(op1.clone(), op, op2)
-        } else {
-            RightHandSide::Variable(op1.clone())
-        };
+        code.reverse();
 
-        while inner.peek().is_some() {
-            let lhs = self.generate_temp(&op1.var_def);
+        Library { functions: self.user_functions.clone(), variables: self.user_variables(), code }
+    }
 
-            self.code.push(Expression::Assignment(exp_str.clone(), Assignment{lhs:lhs.clone(), rhs}));
+    /// Adds the functions and variables of a library to this script, with an error for each one
+    /// that is already defined. The library's code runs first, with its temps after this script's.
+    pub fn import_library(&mut self, lib: &Library) -> Result<(), Vec<ParseError>> {
+        let mut errors = Vec::new();
+        let mut code = lib.code.clone();
+        let mut renamed = HashMap::new();
 
-            let op1 = lhs;
+        for e in code.iter_mut() {
+            for v in e.variables_mut().into_iter().filter(|v| v.is_temp()) {
+                v.name = renamed.entry(v.name.clone()).or_insert_with(|| self.generate_temp(&v.var_def).name).clone();
+            }
+        }
 
-            let op_rule = inner.next().unwrap();
-            let op = match op_rule.as_str() {
-                "+" => Operator::Add,
-                "-" => Operator::Sub,
-                "*" => Operator::Mul,
-                "/" => Operator::Div,
-                _ => return Err(ParseError::new(expression, format!("Unknown operator {}", op_rule.as_str())))
-            };
+        self.code.splice(0..0, code);
 
-            let op2 = self.process_primary(inner.next().unwrap())?;
+        for (name, fun) in &lib.functions {
+            if let Some(builtin) = self.builtins.get(name) {
+                errors.push(ParseError::without_location(ErrorKind::BuiltinRedefinition, name, format!("Re-definition of built-in function: {}", builtin.display_header())));
+            } else if let Some(prev) = self.user_functions.get(name) {
+                errors.push(ParseError::without_location(ErrorKind::FunctionRedefinition, name, format!("Function re-definition of {}{}", prev.display_header(), previously("defined", &prev.decl_span))));
+            } else {
+                self.user_functions.insert(name.clone(), fun.clone());
+            }
+        }
 
-            rhs = RightHandSide::Operation(op1, op, op2);
+        for (name, var) in &lib.variables {
+            if let Some(prev) = self.variables.get(name) {
+                errors.push(ParseError::without_location(ErrorKind::Redeclaration, name, format!("Redeclaration of {}{}", prev.display_decl(), previously("declared", &prev.decl_span))));
+            } else {
+                self.variables.insert(name.clone(), var.clone());
+            }
         }
 
-        Ok(rhs)
+        if errors.is_empty() { Ok( () ) } else { Err(errors) }
     }
 
-    fn process_primary(&mut self, primary: Pair<Rule>) -> Result<Variable, ParseError> {
-        let ret_var;
-
-        let p_str = String::from(primary.as_str());
-        let inner = primary.clone().into_inner().next().unwrap();
+    /// Replaces a global variable with a value known before the script runs, like a setting passed in.
+    /// Copies of the variable become copies of the value, and its declaration is removed, unless it is
+    /// still read as an operand or an argument, in which case it is assigned the value instead.
+    pub fn substitute_variable(&mut self, name: &str, value: Term) -> Result<(), SubstError> {
+        let var = match self.variables.get(name) {
+            Some(var) if !var.is_temp() => var.clone(),
+            _ => return Err(SubstError::NotFound(String::from(name)))
+        };
 
-        match inner.as_rule() {
-            Rule::method_call => {
-                let fc = self.process_method_call(inner)?;
+        let value_def = value.var_def();
+        let value = RightHandSide::Term(value);
 
-                if fc.fun.ret_type.is_none() {
-                    return Err(ParseError::new(primary, String::from("Attempting to use a method that not return a value in an expression")))
-                }
+        if value_def != var.var_def {
+            return Err(SubstError::TypeMismatch(var.name, var.var_def, value_def));
+        }
 
-                let lhs = self.generate_temp(&fc.clone().fun.ret_type.unwrap());
+        let assigns = |e: &Expression| matches!(e, Expression::Assignment(_, a) if a.lhs.name == name);
 
-                self.code.push(Expression::Assignment(p_str, Assignment{
-                    lhs: lhs.clone(),
-                    rhs: RightHandSide::FunctionCall(fc)
-                }));
+        if self.code.iter().chain(self.entry_points.values().flatten()).filter(|e| assigns(e)).count() > 1 {
+            return Err(SubstError::NotConstant(var.name));
+        }
 
-                ret_var = lhs;
-            },
-            Rule::fun_call => {
-                let fc = self.process_fun_call(inner)?;
+        // a variable without a declaration in the top-level code, like one of a library, is not a global to substitute
+        let pos = match self.code.iter().position(assigns) {
+            Some(pos) => pos,
+            None => return Err(SubstError::NotFound(var.name))
+        };
 
-                if fc.fun.ret_type.is_none() {
-                    return Err(ParseError::new(primary, String::from("Attempting to call a function that not return a value in an expression")))
+        for e in self.code.iter_mut().chain(self.entry_points.values_mut().flatten()) {
+            if let Expression::Assignment(_, a) = e {
+                match &a.rhs {
+                    RightHandSide::Variable(v) | RightHandSide::Term(Term::Variable(v)) if v.name == name => a.rhs = value.clone(),
+                    _ => { }
                 }
+            }
+        }
 
-                let lhs = self.generate_temp(&fc.clone().fun.ret_type.unwrap());
+        let reads = |code: &Vec<Expression>, name: &str| code.iter().flat_map(|e| e.operands()).any(|v| v.name == name);
 
-                self.code.push(Expression::Assignment(p_str, Assignment{
-                    lhs: lhs.clone(),
-                    rhs: RightHandSide::FunctionCall(fc)
-                }));
+        if reads(&self.code, name) || self.entry_points.values().any(|code| reads(code, name)) {
+            for e in self.code.iter_mut() {
+                if let Expression::Assignment(_, a) = e {
+                    if a.lhs.name == name {
+                        a.rhs = value.clone();
+                    }
+                }
+            }
 
-                ret_var = lhs;
-            },
-            Rule::expression => {
-                let rhs = self.process_expression(inner)?;
+            return Ok( () );
+        }
 
-                let var_def = match rhs.clone() {
-                    RightHandSide::Variable(v) => v.var_def,
-                    RightHandSide::Operation(v, _, _) => v.var_def,
-                    RightHandSide::Term(t) => {
-                        match t {
-                            Term::String(_) => VarDef::from_type(&VarType::String),
-                            Term::Number(_) => VarDef::from_type(&VarType::Number),
-                            Term::Variable(v) => v.var_def.clone()
-                        }
-                    }
-                    RightHandSide::FunctionCall(f) => {
-                        if f.fun.ret_type.is_none() {
-                            return Err(ParseError::new(primary, String::from("Attempting to call a function that not return a value in an expression")))
-                        } else {
-                            f.fun.ret_type.unwrap()
-                        }
-                    }
-                };
+        // the declaration goes, along with the literal it was assigned from
+        if let Expression::Assignment(_, Assignment { rhs: RightHandSide::Variable(temp), .. }) = self.code.remove(pos) {
+            let literal = |e: &Expression| matches!(e, Expression::Assignment(_, a) if a.lhs.name == temp.name && matches!(a.rhs, RightHandSide::Term(_)));
 
-                let lhs = self.generate_temp(&var_def);
+            if temp.is_temp() && !reads(&self.code, &temp.name) {
+                self.code.retain(|e| !literal(e));
+                self.variables.remove(&temp.name);
+            }
+        }
 
-                self.code.push(Expression::Assignment(p_str, Assignment{
-                    lhs: lhs.clone(),
-                    rhs: rhs
-                }));
+        self.variables.remove(name);
 
-                ret_var = lhs;
-            },
-            Rule::term => {
-                let inner = inner.into_inner().next().unwrap();
+        Ok( () )
+    }
 
-                ret_var = match inner.as_rule() {
-                    Rule::identifier => {
-                        let ident = inner.as_str();
+    /// Moves the top-level code generated from start_line to end_line, inclusive, into a new function, and
+    /// calls it in its place. The variables the code reads before assigning become the params, and the
+    /// one variable it assigns that is read afterwards, if any, is returned. A function's value is the
+    /// variable it assigns last, so the code ends by assigning it if it does not already.
+    /// Only the tests call it until an editor command does.
+    #[cfg(test)]
+    pub fn extract_function(&mut self, start_line: usize, end_line: usize, name: &str) -> Result<(), RefactorError> {
+        if self.has_function(name) || self.variables.contains_key(name) {
+            return Err(RefactorError::NameTaken(String::from(name)));
+        }
 
-                        if let Some(v) = self.variables.get(ident) {
-                            v.clone()
-                        } else {
-                            return Err(ParseError::new(primary, String::from(format!("Undefined variable {}", inner.as_str()))))
-                        }
-                    },
-                    Rule::string => {
-                        let term = Term::String(String::from(inner.as_str()));
-                        let lhs = self.generate_temp(&VarDef::from_type(&VarType::String));
+        let overlaps = |e: &Expression| e.location().line <= end_line && start_line <= e.location().end_line;
+        let first = self.code.iter().position(overlaps).ok_or(RefactorError::EmptyRange(start_line, end_line))?;
+        let last = first + self.code[first..].iter().take_while(|e| overlaps(e)).count();
 
-                        self.code.push(Expression::Assignment(p_str, Assignment{
-                            lhs: lhs.clone(),
-                            rhs: RightHandSide::Term(term)
-                        }));
+        // the range cannot split a statement, or be split by one
+        let partial = self.code[first..last].iter().map(|e| e.location())
+            .find(|loc| loc.line < start_line || end_line < loc.end_line)
+            .or_else(|| self.code[last..].iter().map(|e| e.location()).find(|loc| loc.line <= end_line && start_line <= loc.end_line));
 
-                        lhs
-                    },
-                    Rule::number => {
-                        let term = Term::Number(inner.as_str().parse::<f64>().unwrap());
-                        let lhs = self.generate_temp(&VarDef::from_type(&VarType::Number));
+        if let Some(loc) = partial {
+            return Err(RefactorError::AmbiguousSpan(loc.line));
+        }
 
-                        self.code.push(Expression::Assignment(p_str, Assignment{
+        // a call through a variable holding a function reads the variable
+        let reads = |e: &Expression| -> Vec<Variable> {
+            let call = match e {
+                Expression::FunctionCall(_, fc) | Expression::Assignment(_, Assignment { rhs: RightHandSide::FunctionCall(fc), .. }) => Some(&fc.fun.name),
+                _ => None
+            };
+            let fun_var = call.and_then(|name| self.variables.get(name)).filter(|v| matches!(v.var_def.var_type, VarType::Fun(..)));
+
+            e.operands().into_iter().chain(fun_var).cloned().collect()
+        };
+
+        let mut params = Vec::<Variable>::new();
+        let mut assigned = Vec::<Variable>::new();
+
+        // the branches of a conditional come after it, so it reads only its condition
+        for e in self.code[first..last].iter().flat_map(|e| e.flatten()) {
+            let vars = match e {
+                Expression::Conditional(_, c) => vec![c.cond.clone()],
+                e => reads(e)
+            };
+
+            for var in vars {
+                if !assigned.iter().chain(&params).any(|v| v.name == var.name) {
+                    params.push(var);
+                }
+            }
+
+            if let Expression::Assignment(_, a) = e {
+                if !assigned.iter().any(|v| v.name == a.lhs.name) {
+                    assigned.push(a.lhs.clone());
+                }
+            }
+        }
+
+        let read_after = self.code[last..].iter().chain(self.entry_points.values().flatten()).flat_map(&reads).map(|v| v.name).collect::<HashSet<_>>();
+        let outputs = assigned.iter().filter(|v| read_after.contains(&v.name)).collect::<Vec<_>>();
+
+        // a temp carries a value within a statement, so one crossing the range means it splits a statement
+        if let Some(temp) = params.iter().chain(outputs.iter().cloned()).find(|v| v.is_temp()) {
+            let line = self.code.iter().find(|e| matches!(e, Expression::Assignment(_, a) if a.lhs.name == temp.name)).map_or(start_line, |e| e.location().line);
+
+            return Err(RefactorError::AmbiguousSpan(line));
+        }
+
+        if outputs.len() > 1 {
+            return Err(RefactorError::MultipleOutputs(outputs.iter().map(|v| v.name.clone()).collect()));
+        }
+
+        let output = outputs.first().map(|v| (*v).clone());
+        let mut code = self.code.drain(first..last).collect::<Vec<_>>();
+        let mut loc = code[0].location().clone();
+
+        loc.end_line = code.iter().map(|e| e.location().end_line).max().unwrap_or(loc.line);
+
+        if let Some(output) = &output {
+            if !matches!(code.last(), Some(Expression::Assignment(_, a)) if a.lhs.name == output.name) {
+                code.push(Expression::Assignment(loc.clone(), Assignment { lhs: output.clone(), rhs: RightHandSide::Variable(output.clone()) }));
+            }
+        }
+
+        let mut fun = Function::new(name, params.iter().map(|p| Variable::from_def(&p.name, p.var_def.clone())).collect(), output.as_ref().map(|o| o.var_def.clone()));
+
+        fun.code = code;
+
+        let call = FunctionCall { fun: fun.clone(), var_list: params, named: Vec::new(), broadcast: false };
+
+        loc.text = call.to_source_with(&|v| v.name.clone());
+
+        // what the code assigned is now local to the function, except the value it returns
+        for var in assigned.iter().filter(|v| !matches!(&output, Some(o) if o.name == v.name)) {
+            let declared_in_range = var.decl_span.map_or(var.is_temp(), |(line, _, _)| start_line <= line && line <= end_line);
+
+            if declared_in_range {
+                self.variables.remove(&var.name);
+            }
+        }
+
+        let replacement = match output {
+            Some(lhs) => Expression::Assignment(loc, Assignment { lhs, rhs: RightHandSide::FunctionCall(call) }),
+            None => Expression::FunctionCall(loc, call)
+        };
+
+        self.code.insert(first, replacement);
+        self.user_functions.insert(String::from(name), fun);
+
+        Ok( () )
+    }
+
+    /// Replaces the call to fn_name at code[expression_index] with the code of the function. The params
+    /// read the arguments, except params the function assigns, which are copied into locals first, so the
+    /// arguments are left alone, as are functions passed as temps. The function's other variables get names unused in the top-level code.
+    /// A call assigned to a variable assigns it the function's value, the variable other than a temp it assigns last.
+    /// Only the tests call it until an editor command does.
+    #[cfg(test)]
+    pub fn inline_function(&mut self, expression_index: usize, fn_name: &str) -> Result<(), RefactorError> {
+        let not_a_call = || RefactorError::NotACall(expression_index, String::from(fn_name));
+
+        let (loc, lhs, call) = match self.code.get(expression_index) {
+            Some(Expression::FunctionCall(loc, fc)) => (loc.clone(), None, fc.clone()),
+            Some(Expression::Assignment(loc, Assignment { lhs, rhs: RightHandSide::FunctionCall(fc) })) => (loc.clone(), Some(lhs.clone()), fc.clone()),
+            _ => return Err(not_a_call())
+        };
+
+        // a call through a variable holding a function is not a call to the function of that name
+        if call.fun.name != fn_name || self.variables.contains_key(fn_name) {
+            return Err(not_a_call());
+        }
+
+        let fun = self.user_functions.get(fn_name).cloned().ok_or_else(|| RefactorError::UnknownFunction(String::from(fn_name)))?;
+
+        if fun.is_variadic {
+            return Err(RefactorError::VariadicFunction(String::from(fn_name)));
+        }
+
+        let assigned = fun.code.iter().flat_map(|e| e.flatten()).filter_map(|e| match e {
+            Expression::Assignment(_, a) => Some(a.lhs.name.clone()),
+            _ => None
+        }).collect::<HashSet<_>>();
+
+        let assigns_value = fun.code.iter().any(|e| matches!(e, Expression::Assignment(_, a) if !a.lhs.is_temp()));
+
+        if lhs.is_some() && !assigns_value {
+            return Err(RefactorError::NoValue(String::from(fn_name)));
+        }
+
+        let mut renamed = HashMap::<String, Variable>::new();
+        let mut code = Vec::new();
+
+        // a function called through a temp could not be written as source, so it goes in a local too
+        for (param, arg) in fun.params.iter().zip(&call.var_list) {
+            if assigned.contains(&param.name) || (arg.is_temp() && matches!(param.var_def.var_type, VarType::Fun(..))) {
+                let local = self.fresh_local(fn_name, param);
+
+                code.push(Expression::Assignment(loc.clone(), Assignment { lhs: local.clone(), rhs: RightHandSide::Variable(arg.clone()) }));
+                renamed.insert(param.name.clone(), local);
+            } else {
+                renamed.insert(param.name.clone(), arg.clone());
+            }
+        }
+
+        for e in &fun.code {
+            let mut e = e.clone();
+
+            for l in e.locations_mut() {
+                *l = loc.clone();
+            }
+
+            for v in e.variables_mut() {
+                let new = match renamed.get(&v.name) {
+                    Some(new) => new.clone(),
+                    None => {
+                        let new = if v.is_temp() { self.generate_temp(&v.var_def) } else { self.fresh_local(fn_name, v) };
+
+                        renamed.insert(v.name.clone(), new.clone());
+                        new
+                    }
+                };
+
+                *v = new;
+            }
+
+            // a call through a variable holding a function is named after the variable
+            if let Expression::FunctionCall(_, fc) | Expression::Assignment(_, Assignment { rhs: RightHandSide::FunctionCall(fc), .. }) = &mut e {
+                if let Some(var) = renamed.get(&fc.fun.name).filter(|v| matches!(v.var_def.var_type, VarType::Fun(..))) {
+                    fc.fun.name = var.name.clone();
+                }
+            }
+
+            code.push(e);
+        }
+
+        if let Some(lhs) = lhs {
+            // a temp only carries a value within a statement, so it is never the function's value
+            let value = code.iter().rev().find_map(|e| match e {
+                Expression::Assignment(_, a) if !a.lhs.is_temp() => Some(a.lhs.clone()),
+                _ => None
+            }).ok_or_else(|| RefactorError::NoValue(String::from(fn_name)))?;
+
+            // a temp holding the call is read once, by the next expression, which can read the value instead
+            let mut inlined = false;
+
+            if let (true, Some(next)) = (lhs.is_temp(), self.code.get_mut(expression_index + 1)) {
+                inlined = next.inline(&lhs, &RightHandSide::Variable(value.clone()));
+            }
+
+            if inlined {
+                self.variables.remove(&lhs.name);
+            } else {
+                code.push(Expression::Assignment(loc, Assignment { lhs, rhs: RightHandSide::Variable(value) }));
+            }
+        }
+
+        self.code.splice(expression_index..=expression_index, code);
+
+        Ok( () )
+    }
+
+    /// Declares a global variable for a local of the function being inlined, named after both and not already taken
+    #[cfg(test)]
+    fn fresh_local(&mut self, fn_name: &str, var: &Variable) -> Variable {
+        let base = format!("{}_{}", fn_name, var.name);
+        let name = (1..).map(|i| if i == 1 { base.clone() } else { format!("{}_{}", base, i) })
+            .find(|name| !self.variables.contains_key(name) && !self.has_function(name))
+            .unwrap();
+
+        let local = Variable::from_def(&name, var.var_def.clone());
+
+        self.variables.insert(name, local.clone());
+        local
+    }
+
+    /// Removes temps that only carry a value from one expression to the next,
+    /// in both the top-level code and the code of each function
+    pub fn inline_single_use_temps(&mut self) {
+        for name in inline_temps(&mut self.code) {
+            self.variables.remove(&name);
+        }
+
+        for fun in self.user_functions.values_mut() {
+            inline_temps(&mut fun.code);
+        }
+    }
+
+    /// Renumbers the temps from _t1, in the order they first appear in all_expressions(), so the
+    /// numbering has no gaps and new temps carry on after the last one
+    pub fn reset_temps(&mut self) {
+        let mut renamed = HashMap::new();
+
+        for e in self.all_expressions_mut() {
+            for v in e.variables_mut().into_iter().filter(|v| v.is_temp()) {
+                let next = renamed.len() + 1;
+                v.name = renamed.entry(v.name.clone()).or_insert_with(|| format!("_t{}", next)).clone();
+            }
+        }
+
+        let temps = self.variables.iter().filter(|(_, v)| v.is_temp()).map(|(k, _)| k.clone()).collect::<Vec<_>>();
+        let temps = temps.into_iter().map(|old| self.variables.remove(&old).unwrap()).collect::<Vec<_>>();
+
+        // all of the old names are removed first, as a new name can be the old name of another temp.
+        // Temps no longer in any expression, like ones inlined away, are dropped.
+        for mut temp in temps {
+            if let Some(new) = renamed.get(&temp.name) {
+                temp.name = new.clone();
+                self.variables.insert(new.clone(), temp);
+            }
+        }
+
+        self.tmp_num = renamed.len();
+    }
+
+    /// A copy of the script with its temps renumbered, see reset_temps(), that shares nothing
+    /// with this one, so each copy can be run on its own
+    pub fn fresh_clone(&self) -> Script {
+        let mut script = self.clone();
+
+        script.reset_temps();
+        script
+    }
+
+    /// Renders the functions, sorted by name, then the top-level code as three-address code
+    pub fn emit_ir(&self) -> String {
+        let mut emitter = IrEmitter::new();
+        let mut names = self.user_functions.keys().collect::<Vec<_>>();
+
+        names.sort();
+
+        for name in names {
+            emitter.emit_function(&self.user_functions[name]);
+        }
+
+        emitter.emit_code(&self.code);
+
+        let mut names = self.entry_points.keys().collect::<Vec<_>>();
+
+        names.sort();
+
+        for name in names {
+            emitter.emit_entry_point(name, &self.entry_points[name]);
+        }
+
+        emitter.finish()
+    }
+
+    /// Renders the script back into source: the functions, then the top-level code, then the entry points,
+    /// each in the order they were written. Parsing it gives the same code again, down to the temps.
+    /// The statements of a block used as a value are written in place, so its declarations are no longer scoped to it.
+    pub fn to_source(&self) -> String {
+        let mut emitter = SourceEmitter::new();
+        let mut functions = self.user_functions.values().collect::<Vec<_>>();
+        let mut entry_points = self.entry_points.iter().collect::<Vec<_>>();
+
+        functions.sort_by_key(|f| f.decl_span);
+        entry_points.sort_by_key(|(_, code)| code.first().map(|e| e.location().line));
+
+        for fun in functions {
+            emitter.emit_function(fun);
+        }
+
+        let globals = emitter.emit_code(&self.code, HashSet::new());
+
+        for (name, code) in entry_points {
+            emitter.emit_entry_point(name, code, globals.clone());
+        }
+
+        emitter.finish()
+    }
+
+    /// Generates Markdown documentation of the user functions, in the order they are declared:
+    /// a heading with the signature of each, followed by its docstring if it has one
+    pub fn generate_docs(&self) -> String {
+        let mut functions = self.user_functions.values().collect::<Vec<_>>();
+
+        functions.sort_by_key(|f| f.decl_span);
+
+        let sections = functions.iter().map(|fun| match &fun.docstring {
+            Some(doc) => format!("## {}\n\n{}\n", fun.display_signature(), doc),
+            None => format!("## {}\n", fun.display_signature())
+        }).collect::<Vec<_>>();
+
+        sections.join("\n")
+    }
+
+    /// Returns the names of the @name entry points, sorted
+    pub fn entry_point_names(&self) -> Vec<&String> {
+        let mut names = self.entry_points.keys().collect::<Vec<_>>();
+
+        names.sort();
+        names
+    }
+
+    /// Returns the code that runs for an entry point: the @main block when there
+    /// is one and no name is given, otherwise the unlabeled top-level code
+    pub fn entry_point(&self, name: Option<&str>) -> Option<&Vec<Expression>> {
+        match name {
+            Some(name) => self.entry_points.get(name),
+            None => Some(self.entry_points.get("main").unwrap_or(&self.code))
+        }
+    }
+
+    /// Runs the code of the entry point, see entry_point(), and args are the values returned by the args() built-in
+    pub fn run(&self, entry: Option<&str>, _args: &[String]) -> Result<(), ParseError> {
+        let name = entry.unwrap_or("main");
+
+        self.entry_point(entry).ok_or_else(|| ParseError::without_location(ErrorKind::UnknownEntryPoint, name, format!("Unknown entry point @{}", name)))?;
+
+        Ok( () )
+    }
+
+    /// Adds a warning for each parameter of the fun rule that has the same name as a global variable.
+    /// Inside the function the parameter wins, so the global is never touched by the function.
+    fn check_shadowed_params(&mut self, fun: Pair<Rule>) {
+        let mut signature = fun.into_inner().find(|p| p.as_rule() == Rule::fun_signature).unwrap().into_inner();
+        let fun_name = signature.next().unwrap().as_str();
+
+        for param_list in signature.filter(|p| p.as_rule() == Rule::param_list) {
+            // the var_dec of a param, or the name of a variadic param
+            for decl in param_list.into_inner().map(|p| p.into_inner().next().unwrap()) {
+                let name = decl.clone().into_inner().next().map_or(decl.as_str(), |ident| ident.as_str());
+
+                if self.variables.contains_key(name) {
+                    self.warnings.push(Warning::new(decl, WarningKind::ShadowedParameter, format!("Parameter {} of function {} shadows global variable {}", name, fun_name, name)));
+                }
+            }
+        }
+    }
+
+    /// Processes an @name block into its own code. It can read and assign the
+    /// global variables, but anything it declares is local to the block.
+    fn process_labeled_block(&mut self, labeled_block: Pair<Rule>) -> Result<(String, Vec<Expression>), ParseError> {
+        let mut inner = labeled_block.clone().into_inner();
+
+        let name = String::from(next_pair(&mut inner, &labeled_block)?.as_str());
+        let block = next_pair(&mut inner, &labeled_block)?.into_inner();
+
+        let globals = self.variables.clone();
+        let main_code = std::mem::take(&mut self.code);
+
+        let res = block.map(|pl| self.process_program_line(pl)).collect::<Result<Vec<_>, _>>();
+
+        self.variables = globals;
+        let code = std::mem::replace(&mut self.code, main_code);
+
+        res?;
+
+        Ok( (name, code) )
+    }
+
+    fn process_fun(&mut self, fun: Pair<Rule>) -> Result<Function, ParseError> {
+        let fun_str = String::from(fun.as_str());
+        let mut inner = fun.clone().into_inner();
+
+        // each /// line is a line of the docstring, with the slashes and the space after them removed
+        let mut doc_lines = Vec::new();
+
+        while let Some(doc) = inner.peek().filter(|p| p.as_rule() == Rule::doc_comment) {
+            let line = doc.as_str()[3..].trim_end();
+
+            doc_lines.push(String::from(line.strip_prefix(' ').unwrap_or(line)));
+            inner.next();
+        }
+
+        let docstring = if doc_lines.is_empty() { None } else { Some(doc_lines.join("\n")) };
+
+        let signature = next_pair(&mut inner, &fun)?;
+        let mut params = signature.clone().into_inner();
+
+        let fun_ident = next_pair(&mut params, &signature)?;
+        let fun_name = String::from(fun_ident.as_str());
+
+        let mut param_list = Vec::new();
+        let mut defaults = Vec::new();
+        let mut is_variadic = false;
+        let mut ret_val = Option::None;
+
+        for next in params {
+            match next.as_rule() {
+                Rule::param_list => {
+                    for param in next.into_inner() {
+                        let mut inner = param.clone().into_inner();
+
+                        if param.as_rule() == Rule::variadic_param {
+                            let ident = next_pair(&mut inner, &param)?;
+                            let var_type = VarType::new(next_pair(&mut inner, &param)?);
+                            let var = Variable { name: String::from(ident.as_str()), var_def: VarDef::from_array(&var_type), decl_span: Some(decl_span(&ident)), is_const: false };
+
+                            if !defaults.is_empty() {
+                                return Err(ParseError::new(param, ErrorKind::NonTrailingDefault, format!("Parameter {}:{}... of {} cannot come after a parameter with a default value", var.name, var_type, fun_name)));
+                            }
+
+                            param_list.push(var);
+                            is_variadic = true;
+                            continue;
+                        }
+
+                        let var = Variable::new(next_pair(&mut inner, &param)?);
+
+                        match inner.next() {
+                            Some(default) => {
+                                // only a literal has a type before the function is called
+                                if let Some(term) = literal_term(&default)? {
+                                    if !term.var_def().is_compatible_with(&var.var_def) {
+                                        return Err(ParseError::new(param, ErrorKind::TypeMismatch, format!("Default value {} of {} is a {}, but {} is a {}", term.to_source(), var.name, term.var_def(), var.name, var.var_def)));
+                                    }
+                                }
+
+                                defaults.push(String::from(default.as_str()));
+                            },
+                            None if !defaults.is_empty() => {
+                                return Err(ParseError::new(param, ErrorKind::NonTrailingDefault, format!("Parameter {} of {} needs a default value, as it comes after a parameter with one", var.display_decl(), fun_name)));
+                            },
+                            None => { }
+                        }
+
+                        param_list.push(var);
+                    }
+                },
+                Rule::var_def => {
+                    ret_val = Some(VarDef::new(next));
+                },
+                _ => { return Err(ParseError::new(fun, ErrorKind::UnexpectedToken, format!("Unexpected token: {:?}", next))) }
+            }
+        }
+
+        let block = next_pair(&mut inner, &fun)?.into_inner();
+
+        // the body gets its own scope: the parameters shadow anything with the same name,
+        // and the code goes to the function instead of the "main" code
+        let fun_vars = param_list.iter().map(|v| (v.name.clone(), v.clone())).collect::<SymbolTable>();
+        let globals = std::mem::replace(&mut self.variables, fun_vars);
+        let main_code = std::mem::take(&mut self.code);
+
+        let res = block.map(|pl| self.process_program_line(pl)).collect::<Result<Vec<_>, _>>();
+
+        self.variables = globals;
+        let fun_code = std::mem::replace(&mut self.code, main_code);
+
+        res?;
+
+        Ok( Function {
+            name: fun_name,
+            params: param_list,
+            ret_type: ret_val,
+            defaults,
+            is_variadic,
+            is_noreturn: false,
+            docstring,
+            decl_span: Some(decl_span(&fun_ident)),
+            code: fun_code
+        } )
+    }
+
+    fn process_program_line(&mut self, program_line: Pair<Rule>) -> Result<(), ParseError> {
+        let program_line = program_line.into_inner().next().unwrap();
+        let loc = Location::from_pair(&program_line);
+
+        match program_line.as_rule() {
+            Rule::declaration => {
+                // var_def, expression
+                let mut inner = program_line.clone().into_inner();
+
+                let var_def = inner.next().unwrap();
+                let lhs = Variable::new(var_def);
+
+                if let Some(prev) = self.variables.insert(lhs.name.clone(), lhs.clone()) {
+                    return Err(ParseError::new(program_line, ErrorKind::Redeclaration, format!("Redeclaration of {}{}", prev.display_decl(), previously("declared", &prev.decl_span))));
+                }
+
+                debug!("Declared variable: {:?}", lhs);
+
+                // process the expression on the right-hand-side
+                let rhs = self.process_assigned_value(&lhs, inner.next().unwrap())?;
+
+                self.code.push(Expression::Assignment(loc, Assignment{ lhs, rhs }));
+            },
+            Rule::const_declaration => {
+                // var_def, expression
+                let mut inner = program_line.clone().into_inner();
+
+                let lhs = Variable { is_const: true, ..Variable::new(inner.next().unwrap()) };
+
+                // only a literal or another const, so the value is known before the script runs
+                let expression = match inner.next() {
+                    Some(expression) if self.is_constant(&expression)? => expression,
+                    Some(expression) => return Err(ParseError::new(expression, ErrorKind::InvalidConst, format!("The value of constant {} must be a literal or another constant", lhs.name))),
+                    None => return Err(ParseError::new(program_line, ErrorKind::InvalidConst, format!("Constant {} needs a value", lhs.display_decl())))
+                };
+
+                if let Some(prev) = self.variables.insert(lhs.name.clone(), lhs.clone()) {
+                    return Err(ParseError::new(program_line, ErrorKind::Redeclaration, format!("Redeclaration of {}{}", prev.display_decl(), previously("declared", &prev.decl_span))));
+                }
+
+                let rhs = self.process_assigned_value(&lhs, expression)?;
+
+                self.code.push(Expression::Assignment(loc, Assignment{ lhs, rhs }));
+            },
+            Rule::capture_declaration => {
+                // var_def, expression
+                let mut inner = program_line.clone().into_inner();
+
+                let lhs = Variable::new(inner.next().unwrap());
+
+                if let Some(prev) = self.variables.insert(lhs.name.clone(), lhs.clone()) {
+                    return Err(ParseError::new(program_line, ErrorKind::Redeclaration, format!("Redeclaration of {}{}", prev.display_decl(), previously("declared", &prev.decl_span))));
+                }
+
+                let rhs = self.process_capture(&program_line, &lhs, inner.next().unwrap())?;
+
+                self.code.push(Expression::Assignment(loc, Assignment{ lhs, rhs }));
+            },
+            Rule::capture_assignment => {
+                // identifier, expression
+                let mut inner = program_line.clone().into_inner();
+
+                let ident = inner.next().unwrap().as_str();
+                let expression = inner.next().unwrap();
+
+                let var = match self.variables.get(ident) {
+                    Some(var) => var.clone(),
+                    None => return Err(ParseError::new(program_line, ErrorKind::UndeclaredAssignment, format!("Assignment to undeclared variable: {}, declare it with: var {}:str <- {}", ident, ident, expression.as_str())))
+                };
+
+                if var.is_const {
+                    return Err(ParseError::new(program_line, ErrorKind::ConstAssignment, format!("Assignment to constant {}{}", var.display_decl(), previously("declared", &var.decl_span))));
+                }
+
+                let rhs = self.process_capture(&program_line, &var, expression)?;
+
+                self.code.push(Expression::Assignment(loc, Assignment{ lhs: var, rhs }));
+            },
+            Rule::assignment => {
+                // identifier, expression
+                let mut inner = program_line.clone().into_inner();
+
+                let ident = inner.next().unwrap().as_str().trim();
+                let expression = inner.next().unwrap();
+
+                // check to make sure we've previously declared this variable,
+                // suggesting a declaration with the type of the value when it has one
+                if !self.variables.contains_key(ident) {
+                    let var_def = self.process_expression(expression.clone()).ok().and_then(|rhs| rhs.var_def());
+                    let var_type = var_def.map_or(String::from("<type>"), |v| v.to_string());
+
+                    return Err(ParseError::new(program_line, ErrorKind::UndeclaredAssignment, format!("Assignment to undeclared variable: {}, declare it with: var {}:{} = {}", ident, ident, var_type, expression.as_str())));
+                }
+
+                let var = self.variables.get(ident).unwrap().clone();
+
+                if var.is_const {
+                    return Err(ParseError::new(program_line, ErrorKind::ConstAssignment, format!("Assignment to constant {}{}", var.display_decl(), previously("declared", &var.decl_span))));
+                }
+
+                let rhs = self.process_assigned_value(&var, expression)?;
+
+                self.code.push(Expression::Assignment(loc, Assignment {lhs:var.clone(), rhs}));
+            },
+            Rule::index_assignment => {
+                // index, expression
+                let mut inner = program_line.clone().into_inner();
+
+                let (map, key) = self.process_index(inner.next().unwrap())?;
+                let value_type = map_values(&map.var_def).unwrap();
+
+                self.empty_map = value_type.map_value().cloned();
+                let value = self.process_argument(inner.next().unwrap());
+                self.empty_map = None;
+
+                let value = value?;
+
+                if value.var_def != VarDef::from_type(&value_type) {
+                    return Err(ParseError::new(program_line, ErrorKind::TypeMismatch, format!("Cannot store a {} in {}, a {}", value.var_def, map.name, map.var_def)));
+                }
+
+                self.code.push(Expression::IndexAssignment(loc, IndexAssignment { map, key, value }));
+            },
+            Rule::method_call => {
+                let fun_call = self.process_method_call(program_line.clone())?;
+
+                self.check_unused_result(program_line, &fun_call.fun);
+                self.code.push(Expression::FunctionCall(loc, fun_call));
+            },
+            Rule::fun_call => {
+                let fun_call = self.process_fun_call(program_line.clone(), None)?;
+
+                self.check_unused_result(program_line, &fun_call.fun);
+                self.code.push(Expression::FunctionCall(loc, fun_call));
+            },
+            Rule::expression => {
+                let calls = program_line.clone().into_inner().flatten().any(|p| p.as_rule() == Rule::fun_call);
+                let conditional = program_line.clone().into_inner().any(|p| p.as_rule() == Rule::conditional);
+
+                // a statement made only of literals and variables does nothing, while one choosing which call to make, like
+                // "verbose ? run(a) : run(b);", is for the effect of the call
+                if conditional && calls {
+                } else if !calls {
+                    self.warnings.push(Warning::new(program_line.clone(), WarningKind::NoEffect, String::from("Statement has no effect")));
+                } else if let Some(fun) = self.outermost_call(program_line.clone()) {
+                    self.check_unused_result(program_line.clone(), &fun);
+                } else {
+                    self.warnings.push(Warning::new(program_line.clone(), WarningKind::UnusedResult, String::from("Result of expression is unused")));
+                }
+
+                // lower it like any other expression, the resulting temp is simply never read
+                let rhs = self.process_expression(program_line)?;
+
+                if let RightHandSide::Operation(op1, _, _) = &rhs {
+                    let lhs = self.generate_temp(&op1.var_def);
+
+                    self.code.push(Expression::Assignment(loc, Assignment{ lhs, rhs }));
+                }
+            },
+            _ => {
+                return Err(ParseError::new(program_line, ErrorKind::UnexpectedToken, String::from("Unknown program line")));
+            }
+        };
+
+        Ok( () )
+    }
+
+    /// Processes the command of "lhs <- command", which lowers to capture(command), taking its
+    /// "stdout", and removing the newlines at the end with str_trim_newlines, the value assigned
+    fn process_capture(&mut self, statement: &Pair<Rule>, lhs: &Variable, command: Pair<Rule>) -> Result<RightHandSide, ParseError> {
+        let str_type = VarDef::from_type(&VarType::String);
+        let loc = Location::from_pair(&command);
+
+        if lhs.var_def != str_type {
+            return Err(ParseError::new(statement.clone(), ErrorKind::TypeMismatch, format!("The output of a command is a str, but {} is a {}", lhs.name, lhs.var_def)));
+        }
+
+        let cmd = self.process_argument(command.clone())?;
+
+        if cmd.var_def != str_type {
+            return Err(ParseError::new(command, ErrorKind::TypeMismatch, format!("The command run with <- must be a str, but it is a {}", cmd.var_def)));
+        }
+
+        let builtin = |name: &str| self.builtins.get(name).cloned()
+            .ok_or_else(|| ParseError::new(statement.clone(), ErrorKind::UnknownFunction, format!("Unknown function {}, which <- runs the command with", name)));
+        let capture = builtin("capture")?;
+        let trim = builtin("str_trim_newlines")?;
+
+        if let Some(exec) = capture.params.last() {
+            self.check_literal_argument(statement, &capture, exec, &cmd)?;
+        }
+
+        let output = self.generate_temp(&VarDef::from_type(&VarType::Map(Box::new(VarType::String))));
+        let call = FunctionCall { fun: capture, var_list: vec![cmd], named: Vec::new(), broadcast: false };
+
+        self.code.push(Expression::Assignment(loc.clone(), Assignment { lhs: output.clone(), rhs: RightHandSide::FunctionCall(call) }));
+
+        let key = self.generate_temp(&str_type);
+
+        self.code.push(Expression::Assignment(loc.clone(), Assignment { lhs: key.clone(), rhs: RightHandSide::Term(Term::String(String::from("\"stdout\""))) }));
+
+        let stdout = self.generate_temp(&str_type);
+
+        self.code.push(Expression::Assignment(loc, Assignment { lhs: stdout.clone(), rhs: RightHandSide::Index(output, key) }));
+
+        Ok(RightHandSide::FunctionCall(FunctionCall { fun: trim, var_list: vec![stdout], named: Vec::new(), broadcast: false }))
+    }
+
+    /// Returns true if the expression is nothing more than a literal or a constant
+    fn is_constant(&self, expression: &Pair<Rule>) -> Result<bool, ParseError> {
+        if literal_term(expression)?.is_some() {
+            return Ok(true);
+        }
+
+        let parts = expression.clone().into_inner().flatten().filter(|p| !matches!(p.as_rule(), Rule::primary | Rule::term)).collect::<Vec<_>>();
+
+        match parts.as_slice() {
+            [ident] if ident.as_rule() == Rule::identifier => Ok(matches!(self.variables.get(ident.as_str()), Some(v) if v.is_const)),
+            _ => Ok(false)
+        }
+    }
+
+    /// Processes the expression assigned to lhs. When lhs is a map, an empty map literal
+    /// takes its type, and any other map must have the same type of values.
+    /// When lhs is an array, an empty array literal takes its type, and any other array must have the same type.
+    /// When lhs is a function, the function assigned must have the same signature.
+    /// Any other value must have the type of lhs, or be a char assigned to a str.
+    fn process_assigned_value(&mut self, lhs: &Variable, expression: Pair<Rule>) -> Result<RightHandSide, ParseError> {
+        self.empty_map = map_values(&lhs.var_def);
+        self.empty_array = Some(lhs.var_def.var_type.clone()).filter(|_| lhs.var_def.is_array);
+        let rhs = self.process_expression(expression.clone());
+        self.empty_map = None;
+        self.empty_array = None;
+
+        let rhs = rhs?;
+
+        match rhs.var_def() {
+            Some(var_def) if !var_def.is_compatible_with(&lhs.var_def) => {
+                Err(ParseError::new(expression, ErrorKind::TypeMismatch, format!("Cannot assign a {} to {}, a {}", var_def, lhs.name, lhs.var_def)))
+            },
+            _ => Ok(rhs)
+        }
+    }
+
+    /// Processes a map literal. The values must all have the same type, which an empty map takes from empty_map.
+    fn process_map_literal(&mut self, map_literal: Pair<Rule>) -> Result<RightHandSide, ParseError> {
+        let outer = self.empty_map.take();
+        let mut value_type = None;
+        let mut entries = Vec::new();
+
+        for entry in map_literal.clone().into_inner() {
+            let mut inner = entry.clone().into_inner();
+            let key = String::from(next_pair(&mut inner, &entry)?.as_str());
+
+            // a value that is itself an empty map takes the type of the values of this one
+            self.empty_map = outer.as_ref().and_then(|t| t.map_value()).cloned();
+            let value = self.process_argument(next_pair(&mut inner, &entry)?);
+            self.empty_map = None;
+
+            let value = value?;
+
+            if value.var_def.is_array {
+                return Err(ParseError::new(entry, ErrorKind::TypeMismatch, format!("Map values cannot be arrays, {} is a {}", key, value.var_def)));
+            }
+
+            match &value_type {
+                Some(t) if *t != value.var_def.var_type => {
+                    return Err(ParseError::new(entry, ErrorKind::TypeMismatch, format!("Map values must all be the same type, {} is a {} but the values before it are {}", key, value.var_def, t)));
+                },
+                Some(_) => { },
+                None => value_type = Some(value.var_def.var_type.clone())
+            }
+
+            entries.push((key, value));
+        }
+
+        match value_type.or(outer) {
+            Some(value_type) => Ok(RightHandSide::MapLiteral(value_type, entries)),
+            None => Err(ParseError::new(map_literal, ErrorKind::UntypedMap, String::from("Cannot tell the type of the values of an empty map, assign it to a declared map instead, like: var m:map[str] = {}")))
+        }
+    }
+
+    /// Processes an array literal. The elements must all have the same type, which an empty array takes from empty_array.
+    fn process_array_literal(&mut self, array_literal: Pair<Rule>) -> Result<RightHandSide, ParseError> {
+        let outer = self.empty_array.take();
+        let mut element_type = None;
+        let mut elements = Vec::new();
+
+        for expression in array_literal.clone().into_inner() {
+            // an element that is an empty map takes the type of the values of the maps in this array
+            self.empty_map = outer.as_ref().and_then(|t| t.map_value()).cloned();
+            let element = self.process_argument(expression.clone());
+            self.empty_map = None;
+
+            let element = element?;
+
+            if element.var_def.is_array {
+                return Err(ParseError::new(expression.clone(), ErrorKind::TypeMismatch, format!("Array elements cannot be arrays, {} is a {}", expression.as_str(), element.var_def)));
+            }
+
+            match &element_type {
+                Some(t) if *t != element.var_def.var_type => {
+                    return Err(ParseError::new(expression.clone(), ErrorKind::TypeMismatch, format!("Array elements must all be the same type, {} is a {} but the elements before it are {}", expression.as_str(), element.var_def, t)));
+                },
+                Some(_) => { },
+                None => element_type = Some(element.var_def.var_type.clone())
+            }
+
+            elements.push(element);
+        }
+
+        match element_type.or(outer) {
+            Some(element_type) => Ok(RightHandSide::ArrayLiteral(element_type, elements)),
+            None => Err(ParseError::new(array_literal, ErrorKind::UntypedArray, String::from("Cannot tell the type of the elements of an empty array, assign it to a declared array instead, like: var a:str[] = []")))
+        }
+    }
+
+    /// Processes the statements of a block_expr rule, returning its final expression
+    fn process_block_expr(&mut self, block_expr: Pair<Rule>) -> Result<RightHandSide, ParseError> {
+        for inner in block_expr.clone().into_inner() {
+            match inner.as_rule() {
+                Rule::program_line => self.process_program_line(inner)?,
+                _ => return self.process_expression(inner)
+            }
+        }
+
+        Err(ParseError::new(block_expr.clone(), ErrorKind::UnexpectedToken, format!("Incomplete block: {}", block_expr.as_str())))
+    }
+
+    /// Processes an index rule into the map and the key read from it, which must be a str
+    fn process_index(&mut self, index: Pair<Rule>) -> Result<(Variable, Variable), ParseError> {
+        let mut inner = index.clone().into_inner();
+        let ident = next_pair(&mut inner, &index)?.as_str();
+
+        let map = match self.variables.get(ident) {
+            Some(var) => var.clone(),
+            None => return Err(ParseError::new(index, ErrorKind::UndefinedVariable, format!("Undefined variable {}", ident)))
+        };
+
+        if map_values(&map.var_def).is_none() {
+            return Err(ParseError::new(index, ErrorKind::IndexOnNonMap, format!("Cannot index {}, it is a {} and only maps can be indexed", ident, map.var_def)));
+        }
+
+        let key = self.process_argument(next_pair(&mut inner, &index)?)?;
+
+        if key.var_def != VarDef::from_type(&VarType::String) {
+            return Err(ParseError::new(index, ErrorKind::TypeMismatch, format!("Map keys must be str, not {}", key.var_def)));
+        }
+
+        Ok( (map, key) )
+    }
+
+    /// Warns when a statement throws away the value returned by a function.
+    /// Pipes are exempt, as running a command only for its side effects is the normal case.
+    fn check_unused_result(&mut self, statement: Pair<Rule>, fun: &Function) {
+        if let Some(ret_type) = &fun.ret_type {
+            if ret_type.var_type != VarType::Pipe {
+                self.warnings.push(Warning::new(statement, WarningKind::UnusedResult, format!("Result of `{}` is unused", fun.name)));
+            }
+        }
+    }
+
+    /// Returns the function whose value the expression evaluates to,
+    /// or None if the expression is an operation or a term
+    fn outermost_call(&self, expression: Pair<Rule>) -> Option<Function> {
+        let mut inner = expression.into_inner();
+        let primary = inner.next()?;
+
+        if inner.next().is_some() {
+            return None; // the value is the result of an operator
+        }
+
+        let primary = primary.into_inner().next()?;
+
+        let fun_call = match primary.as_rule() {
+            Rule::fun_call => primary,
+            Rule::method_call => primary.into_inner().last()?,
+            Rule::expression => return self.outermost_call(primary),
+            _ => return None
+        };
+
+        let name = fun_call.into_inner().next()?.as_str();
+
+        self.get_function(name).cloned()
+    }
+
+    fn process_expression(&mut self, expression: Pair<Rule>) -> Result<RightHandSide, ParseError> {
+        let exp_loc = Location::from_pair(&expression);
+        let mut inner = expression.clone().into_inner();
+
+        let op1 = self.process_primary(next_pair(&mut inner, &expression)?)?;
+        let is_op = |pairs: &Pairs<Rule>| matches!(pairs.peek(), Some(p) if p.as_rule() == Rule::bin_op);
+
+        let mut rhs = if is_op(&inner) {
+            let op_rule = next_pair(&mut inner, &expression)?;
+            let op = match op_rule.as_str() {
+                "+" => Operator::Add,
+                "-" => Operator::Sub,
+                "*" => Operator::Mul,
+                "/" => Operator::Div,
+                _ => return Err(ParseError::new(expression, ErrorKind::UnknownOperator, format!("Unknown operator {}", op_rule.as_str())))
+            };
+
+            let op2 = self.process_primary(next_pair(&mut inner, &expression)?)?;
+
+            if op1.var_def != op2.var_def {
+                return Err(ParseError::new(expression, ErrorKind::TypeMismatch, format!("Attempting to combine values of different types {} != {}", op1.var_def, op2.var_def)))
+            }
+
+            RightHandSide::Operation(op1.clone(), op, op2)
+        } else {
+            RightHandSide::Variable(op1.clone())
+        };
+
+        while is_op(&inner) {
+            let lhs = self.generate_temp(&op1.var_def);
+
+            self.code.push(Expression::Assignment(exp_loc.clone(), Assignment{lhs:lhs.clone(), rhs}));
+
+            let op1 = lhs;
+
+            let op_rule = next_pair(&mut inner, &expression)?;
+            let op = match op_rule.as_str() {
+                "+" => Operator::Add,
+                "-" => Operator::Sub,
+                "*" => Operator::Mul,
+                "/" => Operator::Div,
+                _ => return Err(ParseError::new(expression, ErrorKind::UnknownOperator, format!("Unknown operator {}", op_rule.as_str())))
+            };
+
+            let op2 = self.process_primary(next_pair(&mut inner, &expression)?)?;
+
+            rhs = RightHandSide::Operation(op1, op, op2);
+        }
+
+        match inner.next() {
+            Some(conditional) => self.process_conditional(conditional, exp_loc, rhs),
+            None => Ok(rhs)
+        }
+    }
+
+    /// Processes the "? then : else" after a condition. The code of each branch is kept apart,
+    /// so only the branch chosen runs, and the value is the temp both branches assign to.
+    fn process_conditional(&mut self, conditional: Pair<Rule>, loc: Location, cond: RightHandSide) -> Result<RightHandSide, ParseError> {
+        let cond = match cond {
+            RightHandSide::Variable(v) => v,
+            rhs => {
+                let lhs = self.generate_temp(&rhs.var_def().unwrap());
+
+                self.code.push(Expression::Assignment(loc.clone(), Assignment{ lhs: lhs.clone(), rhs }));
+                lhs
+            }
+        };
+
+        if cond.var_def != VarDef::from_type(&VarType::Number) {
+            return Err(ParseError::new(conditional, ErrorKind::TypeMismatch, format!("The condition of ? : must be a num, but it is a {}", cond.var_def)));
+        }
+
+        let mut inner = conditional.clone().into_inner();
+        let branch = |script: &mut Script, expression: Pair<Rule>| {
+            let start = script.code.len();
+            let value = script.process_expression(expression.clone())?;
+            let var_def = script.value_type(&expression, &value)?;
+
+            Ok::<_, ParseError>((Location::from_pair(&expression), script.code.split_off(start), value, var_def))
+        };
+
+        let (then_loc, mut then_code, then, then_def) = branch(self, next_pair(&mut inner, &conditional)?)?;
+        let (else_loc, mut else_code, other, else_def) = branch(self, next_pair(&mut inner, &conditional)?)?;
+
+        if then_def != else_def {
+            return Err(ParseError::new(conditional, ErrorKind::TypeMismatch, format!("The branches of ? : must have the same type, but one is a {} and the other a {}", then_def, else_def)));
+        }
+
+        let result = self.generate_temp(&then_def);
+
+        then_code.push(Expression::Assignment(then_loc, Assignment { lhs: result.clone(), rhs: then }));
+        else_code.push(Expression::Assignment(else_loc, Assignment { lhs: result.clone(), rhs: other }));
+
+        self.code.push(Expression::Conditional(loc, ConditionalExpr { cond, then_code, else_code, result: result.clone() }));
+
+        Ok(RightHandSide::Variable(result))
+    }
+
+    /// Lowers an f""" string, which reads str variables written as ${name} in its text. It is
+    /// the text and the variables joined with +, lowered the same way as writing them that way.
+    fn process_interpolation(&mut self, literal: Pair<Rule>) -> Result<Variable, ParseError> {
+        let loc = Location::from_pair(&literal);
+        let text = heredoc_text(&literal.as_str()[1..]);
+
+        enum Part<'a> { Text(&'a str), Var(Variable) }
+
+        let mut parts = Vec::new();
+        let mut rest = text.as_str();
+
+        while let Some(start) = rest.find("${") {
+            let name = match rest[start + 2..].find('}') {
+                Some(len) => &rest[start + 2..start + 2 + len],
+                None => return Err(ParseError::new(literal, ErrorKind::Syntax, String::from("Missing the } closing a ${ in an f\"\"\" string")))
+            };
+
+            let var = match self.variables.get(name) {
+                Some(var) => var.clone(),
+                None => return Err(ParseError::new(literal, ErrorKind::UndefinedVariable, format!("Undefined variable {} in an f\"\"\" string", name)))
+            };
+
+            if var.var_def != VarDef::from_type(&VarType::String) {
+                return Err(ParseError::new(literal, ErrorKind::TypeMismatch, format!("Cannot read {}:{} in an f\"\"\" string, only a str", var.name, var.var_def)));
+            }
+
+            parts.push(Part::Text(&rest[..start]));
+            parts.push(Part::Var(var));
+            rest = &rest[start + 2 + name.len() + 1..];
+        }
+
+        parts.push(Part::Text(rest));
+
+        // empty text is left out, unless there is nothing else
+        let mut parts = parts.into_iter().filter(|p| !matches!(p, Part::Text("")));
+        let value = |script: &mut Script, part: Part| match part {
+            Part::Var(var) => var,
+            Part::Text(text) => {
+                let lhs = script.generate_temp(&VarDef::from_type(&VarType::String));
+
+                script.code.push(Expression::Assignment(loc.clone(), Assignment{ lhs: lhs.clone(), rhs: RightHandSide::Term(Term::String(quote(text))) }));
+                lhs
+            }
+        };
+
+        let mut joined = value(self, parts.next().unwrap_or(Part::Text("")));
+
+        for part in parts {
+            let op2 = value(self, part);
+            let lhs = self.generate_temp(&VarDef::from_type(&VarType::String));
+
+            self.code.push(Expression::Assignment(loc.clone(), Assignment{ lhs: lhs.clone(), rhs: RightHandSide::Operation(joined, Operator::Add, op2) }));
+            joined = lhs;
+        }
+
+        Ok(joined)
+    }
+
+    fn process_primary(&mut self, primary: Pair<Rule>) -> Result<Variable, ParseError> {
+        let ret_var;
+
+        let p_loc = Location::from_pair(&primary);
+        let inner = next_pair(&mut primary.clone().into_inner(), &primary)?;
+
+        match inner.as_rule() {
+            Rule::method_call => {
+                let fc = self.process_method_call(inner)?;
+                let ret_type = self.call_value_type(&primary, &fc, "use the value of")?;
+                let lhs = self.generate_temp(&ret_type);
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{
+                    lhs: lhs.clone(),
+                    rhs: RightHandSide::FunctionCall(fc)
+                }));
+
+                ret_var = lhs;
+            },
+            Rule::fun_call => {
+                let fc = self.process_fun_call(inner, None)?;
+                let ret_type = self.call_value_type(&primary, &fc, "use the value of")?;
+                let lhs = self.generate_temp(&ret_type);
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{
+                    lhs: lhs.clone(),
+                    rhs: RightHandSide::FunctionCall(fc)
+                }));
+
+                ret_var = lhs;
+            },
+            Rule::expression => {
+                let rhs = self.process_expression(inner)?;
+
+                let var_def = self.value_type(&primary, &rhs)?;
+
+                let lhs = self.generate_temp(&var_def);
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{
+                    lhs: lhs.clone(),
+                    rhs: rhs
+                }));
+
+                ret_var = lhs;
+            },
+            Rule::map_literal => {
+                let rhs = self.process_map_literal(inner)?;
+                let lhs = self.generate_temp(&rhs.var_def().unwrap());
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{ lhs: lhs.clone(), rhs }));
+
+                ret_var = lhs;
+            },
+            Rule::array_literal => {
+                let rhs = self.process_array_literal(inner)?;
+                let lhs = self.generate_temp(&rhs.var_def().unwrap());
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{ lhs: lhs.clone(), rhs }));
+
+                ret_var = lhs;
+            },
+            Rule::block_expr => {
+                // the block's declarations are only visible inside it, but its code runs in place
+                let scope = self.variables.clone();
+                let rhs = self.process_block_expr(inner);
+
+                self.variables = scope;
+
+                let rhs = rhs?;
+
+                let var_def = match rhs.var_def() {
+                    Some(var_def) => var_def,
+                    None => return Err(ParseError::new(primary, ErrorKind::NoReturnValue, String::from("Attempting to use a block that does not end in a value in an expression")))
+                };
+
+                let lhs = self.generate_temp(&var_def);
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{ lhs: lhs.clone(), rhs }));
+
+                ret_var = lhs;
+            },
+            Rule::index => {
+                let (map, key) = self.process_index(inner)?;
+                let lhs = self.generate_temp(&VarDef::from_type(&map_values(&map.var_def).unwrap()));
+
+                self.code.push(Expression::Assignment(p_loc, Assignment{
+                    lhs: lhs.clone(),
+                    rhs: RightHandSide::Index(map, key)
+                }));
+
+                ret_var = lhs;
+            },
+            Rule::term => {
+                let inner = next_pair(&mut inner.clone().into_inner(), &inner)?;
+
+                ret_var = match inner.as_rule() {
+                    Rule::identifier => {
+                        let ident = inner.as_str();
+
+                        if let Some(v) = self.variables.get(ident) {
+                            v.clone()
+                        } else if let Some(fun) = self.user_functions.get(ident) {
+                            // a reference to a function, so it can be stored and passed like any other value
+                            let var_def = VarDef::from_type(&fun.var_type());
+                            let term = Term::Function(String::from(ident), var_def.clone());
+                            let lhs = self.generate_temp(&var_def);
+
+                            self.code.push(Expression::Assignment(p_loc, Assignment{
+                                lhs: lhs.clone(),
+                                rhs: RightHandSide::Term(term)
+                            }));
+
+                            lhs
+                        } else {
+                            return Err(ParseError::new(primary, ErrorKind::UndefinedVariable, String::from(format!("Undefined variable {}", inner.as_str()))))
+                        }
+                    },
+                    Rule::string => {
+                        let term = Term::String(String::from(inner.as_str()));
+                        let lhs = self.generate_temp(&VarDef::from_type(&VarType::String));
+
+                        self.code.push(Expression::Assignment(p_loc, Assignment{
+                            lhs: lhs.clone(),
+                            rhs: RightHandSide::Term(term)
+                        }));
+
+                        lhs
+                    },
+                    Rule::heredoc => {
+                        let term = Term::from_heredoc(inner.as_str());
+                        let lhs = self.generate_temp(&VarDef::from_type(&VarType::String));
+
+                        self.code.push(Expression::Assignment(p_loc, Assignment{
+                            lhs: lhs.clone(),
+                            rhs: RightHandSide::Term(term)
+                        }));
+
+                        lhs
+                    },
+                    Rule::interpolated_heredoc => self.process_interpolation(inner)?,
+                    Rule::char_literal => {
+                        let term = Term::from_char_literal(inner.as_str());
+                        let lhs = self.generate_temp(&VarDef::from_type(&VarType::Char));
+
+                        self.code.push(Expression::Assignment(p_loc, Assignment{
+                            lhs: lhs.clone(),
+                            rhs: RightHandSide::Term(term)
+                        }));
+
+                        lhs
+                    },
+                    Rule::number => {
+                        let term = Term::Number(parse_number(inner)?);
+                        let lhs = self.generate_temp(&VarDef::from_type(&VarType::Number));
+
+                        self.code.push(Expression::Assignment(p_loc, Assignment{
                             lhs: lhs.clone(),
                             rhs: RightHandSide::Term(term)
                         }));
 
                         lhs
                     },
-                    _ => return Err(ParseError::new(primary, String::from(format!("Unknown term type: {} ({:?})", inner.as_str(), inner.as_rule()))))
+                    _ => return Err(ParseError::new(primary, ErrorKind::UnexpectedToken, String::from(format!("Unknown term type: {} ({:?})", inner.as_str(), inner.as_rule()))))
                 };
             },
-            _ => { return Err(ParseError::new(primary, String::from("Unknown primary"))) }
+            _ => { return Err(ParseError::new(primary, ErrorKind::UnexpectedToken, String::from("Unknown primary"))) }
         }
 
         Ok(ret_var)
     }
 
     fn process_method_call(&mut self, method_call: Pair<Rule>) -> Result<FunctionCall, ParseError> {
-        let mc_str = String::from(method_call.as_str());
+        let mc_loc = Location::from_pair(&method_call);
         // fun_call | identifier, fun_call
         let mut inner = method_call.clone().into_inner();
 
-        let first = inner.next().unwrap(); // either fun_call or ident
+        let first = next_pair(&mut inner, &method_call)?; // either fun_call or ident
         let first_str = first.as_str();
-        let fun_call = inner.next().unwrap();
+        let fun_call = next_pair(&mut inner, &method_call)?;
+        let method = fun_call.clone().into_inner().next().map_or("", |ident| ident.as_str());
 
         let var = match first.as_rule() {
             Rule::identifier => {
-                if let Some(var) = self.variables.get(first_str) {
-                    if let VarType::Pipe = var.var_def.var_type {
-                        var.clone()
-                    } else {
-                        return Err(ParseError::new(method_call, format!("Cannot call a method on a non-pipe variable: {}", first_str)));
+                match self.variables.get(first_str) {
+                    // a pipe array calls the method on each of its pipes
+                    Some(var) if var.var_def.is_array && var.var_def.var_type != VarType::Pipe => {
+                        return Err(ParseError::new(method_call, ErrorKind::MethodOnArray, format!("Cannot call a method on the array {}, a {}, only pipe arrays have methods", first_str, var.var_def)));
+                    },
+                    Some(var) => var.clone(),
+                    None => {
+                        return Err(ParseError::new(method_call, ErrorKind::UndefinedVariable, format!("Unknown variable {}", first_str)));
                     }
-                } else {
-                    return Err(ParseError::new(method_call, format!("Unknown variable {}", first_str)));
                 }
             },
             Rule::fun_call => {
-                let fc = self.process_fun_call(first)?;
-                let ret_type = fc.clone().fun.ret_type;
-
-                if ret_type.is_none() {
-                    return Err(ParseError::new(method_call, format!("Attempting to call a function on a function that does not return a value: {}", first_str)));
-                }
+                let fc = self.process_fun_call(first, None)?;
+                let ret_type = self.call_value_type(&method_call, &fc, &format!("call {} on the value of", method))?;
+                let lhs = self.generate_temp(&ret_type);
 
-                let lhs = self.generate_temp(&ret_type.unwrap());
-
-                self.code.push(Expression::Assignment(mc_str, Assignment {
+                self.code.push(Expression::Assignment(mc_loc, Assignment {
                     lhs: lhs.clone(),
                     rhs: RightHandSide::FunctionCall(fc)
                 }));
 
                 lhs
             },
-            _ => { panic!("Unknown expansion for method_call: {}", mc_str); }
+            _ => { return Err(ParseError::new(method_call, ErrorKind::UnexpectedToken, format!("Unknown expansion for method_call: {}", mc_loc.text))); }
+        };
+
+        if self.builtins.methods().get(&var.var_def, method).is_none() {
+            let receiver = var.var_def.var_type.canonical_name();
+            let names = self.builtins.methods().names(&var.var_def.var_type).into_iter().map(|n| n.as_str()).collect::<Vec<_>>();
+            let available = if names.is_empty() { format!("{} has no methods", receiver) } else { format!("{} has methods: {}", receiver, names.join(", ")) };
+
+            return Err(ParseError::new(method_call, ErrorKind::UnknownMethod, format!("Unknown method {} of {}, {}", method, var.var_def, available)));
+        }
+
+        // the variable is the first argument to the function
+        self.process_fun_call(fun_call, Some(var))
+    }
+
+    /// Returns the type of the value of the call, or an error naming its function when that is declared without a
+    /// return type, along with where a user function is declared. using is what the value would have been used for.
+    fn call_value_type(&self, at: &Pair<Rule>, fc: &FunctionCall, using: &str) -> Result<VarDef, ParseError> {
+        if let Some(ret_type) = &fc.fun.ret_type {
+            return Ok(ret_type.clone());
+        }
+
+        let (kind, line) = match fc.fun.decl_span {
+            Some((line, _, _)) => ("", format!(" at line {}", line)),
+            None if self.builtins.contains(&fc.fun.name) => ("the built-in ", String::new()),
+            None => ("", String::new())
         };
 
-        let mut ret = self.process_fun_call(fun_call)?;
+        Err(ParseError::new(at.clone(), ErrorKind::NoReturnValue, format!("Cannot {} {}, as {}{} is declared without a return type{}", using, fc.fun.name, kind, fc.fun.display_signature(), line)))
+    }
+
+    /// The type of the value of rhs, with the same error as call_value_type() when it is a call without a return type
+    fn value_type(&self, at: &Pair<Rule>, rhs: &RightHandSide) -> Result<VarDef, ParseError> {
+        if let RightHandSide::FunctionCall(fc) = rhs {
+            return self.call_value_type(at, fc, "use the value of");
+        }
+
+        rhs.var_def().ok_or_else(|| ParseError::new(at.clone(), ErrorKind::NoReturnValue, format!("Cannot use the value of {}, it has none", at.as_str())))
+    }
+
+    /// Processes an expression passed as an argument, storing it in a temp if it is not already a variable
+    fn process_argument(&mut self, expression: Pair<Rule>) -> Result<Variable, ParseError> {
+        let loc = Location::from_pair(&expression);
+        let var = self.process_expression(expression.clone())?;
+
+        debug!("VAR LIST VAR: {:?}", var);
+        debug!("VARIABLES: {:?}", self.variables);
+
+        if let RightHandSide::Variable(v) = var {
+            return Ok(v);
+        }
+
+        let var_def = self.value_type(&expression, &var)?;
 
-        // insert the variable as the first argument to the list
-        ret.var_list.insert(0, var);
+        let lhs = self.generate_temp(&var_def);
 
-        Ok(ret)
+        self.code.push(Expression::Assignment(loc, Assignment{ lhs: lhs.clone(), rhs: var }));
+
+        Ok(lhs)
     }
 
-    fn process_fun_call(&mut self, fun_call: Pair<Rule>) -> Result<FunctionCall, ParseError> {
-        let fc_str = fun_call.as_str();
+    /// Processes a call, with the receiver of a method call passed as the first argument
+    fn process_fun_call(&mut self, fun_call: Pair<Rule>, receiver: Option<Variable>) -> Result<FunctionCall, ParseError> {
         let mut inner = fun_call.clone().into_inner();
 
         debug!("INNER: {:?}", inner);
 
-        let name = String::from(inner.next().unwrap().as_str());
+        let name = String::from(next_pair(&mut inner, &fun_call)?.as_str());
+
+        // with a receiver, the name is a method of its type, which process_method_call() has checked
+        let method = receiver.as_ref().and_then(|r| self.builtins.methods().get(&r.var_def, &name)).cloned();
+        let is_builtin = method.is_some() || (!self.variables.contains_key(&name) && !self.user_functions.contains_key(&name));
 
-        let fun = if let Some(fun) = self.user_functions.get(&name) {
+        // a call through a variable holding a function only has the variable's type to go on,
+        // so it gets a function without code, named after the variable, that is resolved when it runs
+        let mut fun = if let Some(method) = method {
+            method
+        } else if let Some(VarType::Fun(params, ret)) = self.variables.get(&name).map(|v| &v.var_def.var_type) {
+            let params = params.iter().enumerate().map(|(i, p)| Variable::from_def(&format!("p{}", i + 1), p.clone())).collect();
+
+            Function::new(&name, params, ret.clone().map(|r| *r))
+        } else if let Some(fun) = self.user_functions.get(&name) {
             fun.clone()
-        } else if let Some(fun) = self.builtin_functions.get(&name) {
+        } else if let Some(fun) = self.builtins.get(&name) {
             fun.clone()
         } else {
-            return Err(ParseError::new(fun_call, String::from(format!("Unknown function {}", name))));
+            return Err(ParseError::new(fun_call, ErrorKind::UnknownFunction, String::from(format!("Unknown function {}", name))));
         };
 
-        let var_list = if inner.peek().is_some() {
-            inner.next().unwrap().into_inner().map(|exp| {
-                let var = self.process_expression(exp).expect("Error processing expression");
-
-                debug!("VAR LIST VAR: {:?}", var);
-                debug!("VARIABLES: {:?}", self.variables);
+        // the receiver of a method call is the first positional argument, the
+        // rest are given either all by position or all by name
+        let mut var_list = receiver.into_iter().collect::<Vec<_>>();
+        let receivers = var_list.len();
+        let mut named = Vec::new();
 
-                match var {
-                    RightHandSide::Variable(v) => v,
-                    _ => panic!("Found non-variable: {:?}", var)
+        for arg in inner.next().into_iter().flat_map(|var_list| var_list.into_inner()) {
+            if arg.as_rule() != Rule::named_arg {
+                if let Some((prev, _, _)) = named.last() {
+                    return Err(ParseError::new(arg.clone(), ErrorKind::MixedArguments, format!("Positional argument {} is mixed with the named argument {}, the arguments of {} must all be positional or all be named", arg.as_str(), prev, name)));
                 }
-            }).collect::<Vec<_>>()
-        } else {
+
+                var_list.push(self.process_argument(arg)?);
+                continue;
+            }
+
+            let mut parts = arg.clone().into_inner();
+            let param_name = next_pair(&mut parts, &arg)?.as_str();
+
+            if var_list.len() > receivers {
+                return Err(ParseError::new(arg, ErrorKind::MixedArguments, format!("Named argument {} is mixed with positional arguments, the arguments of {} must all be positional or all be named", param_name, name)));
+            }
+
+            let index = match fun.params.iter().position(|p| p.name == param_name) {
+                Some(index) if fun.is_variadic && index == fun.params.len() - 1 => {
+                    return Err(ParseError::new(arg, ErrorKind::UnknownParameter, format!("The variadic parameter {} of {} cannot be given by name", param_name, name)));
+                },
+                Some(index) => index,
+                None => return Err(ParseError::new(arg, ErrorKind::UnknownParameter, format!("{} has no parameter named {}", fun.display_header(), param_name)))
+            };
+
+            if index < var_list.len() || named.iter().any(|(_, i, _)| *i == index) {
+                return Err(ParseError::new(arg, ErrorKind::DuplicateArgument, format!("Argument {} of {} is given more than once", param_name, name)));
+            }
+
+            let value = self.process_argument(next_pair(&mut parts, &arg)?)?;
+
+            named.push((param_name, index, value));
+        }
+
+        let named = if named.is_empty() {
             Vec::new()
+        } else {
+            self.place_named_args(&fun_call, &fun, &mut var_list, named)?
         };
 
         // make sure all the variables in the list are known
         for var in &var_list {
             if !self.variables.contains_key(&var.name) {
-                panic!("Unknown variable {} in: {}", var.name, fc_str);
+                return Err(ParseError::new(fun_call, ErrorKind::UndefinedVariable, format!("Unknown variable {}", var.name)));
+            }
+        }
+
+        // trailing arguments that are left out are filled in from the defaults. Built-ins are not
+        // checked otherwise, as run("ls") leaves out the input pipe at the start instead.
+        // Any number of arguments can follow the params before a variadic one.
+        let fixed = fun.params.len() - usize::from(fun.is_variadic);
+        let missing = fun.params.len().saturating_sub(var_list.len());
+        let required = fun.params.len() - fun.defaults.len();
+
+        if fun.is_variadic {
+            if var_list.len() < fixed {
+                return Err(ParseError::new(fun_call, ErrorKind::ArgumentCount, format!("{} takes at least {} arguments, but {} were given", name, fixed, var_list.len())));
+            }
+        } else if missing > 0 && missing <= fun.defaults.len() {
+            for i in var_list.len()..fun.params.len() {
+                let value = self.default_argument(&fun_call, &fun, i)?;
+                var_list.push(value);
+            }
+        } else if !is_builtin && (var_list.len() < required || var_list.len() > fun.params.len()) {
+            let expected = if required == fun.params.len() { required.to_string() } else { format!("{} to {}", required, fun.params.len()) };
+
+            return Err(ParseError::new(fun_call, ErrorKind::ArgumentCount, format!("{} takes {} arguments, but {} were given", name, expected, var_list.len())));
+        }
+
+        // passing a pipe[] to the leading pipe param broadcasts the call: it runs once for each pipe,
+        // and the results are collected into an array, so collect() on a pipe[] is a str[]. Arrays
+        // don't nest, so a call returning an array can't broadcast, except that the pipes of each
+        // call returning a pipe[], like run, are collected into one pipe[]
+        let broadcast = var_list.len() == fun.params.len() && !fun.is_variadic
+            && matches!(fun.params.first(), Some(p) if p.var_def == VarDef::from_type(&VarType::Pipe))
+            && var_list[0].var_def == VarDef::from_array(&VarType::Pipe);
+
+        if broadcast {
+            if let Some(ret_type) = fun.ret_type.as_ref().filter(|r| r.is_array && r.var_type != VarType::Pipe) {
+                return Err(ParseError::new(fun_call, ErrorKind::TypeMismatch, format!("Cannot call {} on each pipe of a pipe[], it returns a {} and arrays don't nest", name, ret_type)));
+            }
+
+            fun.ret_type = fun.ret_type.map(|r| VarDef::from_array(&r.var_type));
+        }
+
+        // a function passed to a function param must have the same signature, and an exec string, JSON, JSON
+        // Pointer, duration, size, or pattern written as a literal can be read now, instead of failing when it runs.
+        // The arguments line up with the last params, as run("ls") leaves out the input pipe.
+        let (params, args) = if fun.is_variadic { (&fun.params[..fixed], &var_list[..fixed]) } else { (&fun.params[..], &var_list[..]) };
+
+        for (param, arg) in params.iter().rev().zip(args.iter().rev()) {
+            if let VarType::Fun(..) = param.var_def.var_type {
+                if arg.var_def != param.var_def {
+                    return Err(ParseError::new(fun_call, ErrorKind::TypeMismatch, format!("Cannot pass a {} as {} of {}, it must be a {}", arg.var_def, param.name, name, param.var_def)));
+                }
+            }
+
+            if is_builtin {
+                self.check_literal_argument(&fun_call, &fun, param, arg)?;
+            }
+        }
+
+        // a group of pipe_match written as a literal must be one named in a pattern written as one
+        let literal = |name: &str| params.iter().rev().zip(args.iter().rev()).find(|(p, _)| p.name == name).and_then(|(_, a)| self.string_literal(a));
+        let literals = if is_builtin && fun.name == "pipe_match" { (literal("pattern"), literal("group")) } else { (None, None) };
+
+        if let (Some(pattern), Some(group)) = literals {
+            let named = matches!(regex::Regex::new(&unquote(&pattern)), Ok(re) if re.capture_names().flatten().any(|n| n == unquote(&group)));
+
+            if !named {
+                return Err(ParseError::new(fun_call, ErrorKind::InvalidPattern, format!("The pattern {} has no group named {}", pattern, group)));
+            }
+        }
+
+        // the arguments left over for a variadic param are each one of its elements
+        if let (true, Some(rest)) = (fun.is_variadic, fun.params.last()) {
+            let element = VarDef::from_type(&rest.var_def.var_type);
+
+            if let Some(arg) = var_list[fixed..].iter().find(|arg| !arg.var_def.is_compatible_with(&element)) {
+                return Err(ParseError::new(fun_call, ErrorKind::TypeMismatch, format!("Cannot pass a {} as part of {} of {}, it must be a {}", arg.var_def, rest.name, name, element)));
+            }
+        }
+
+        Ok(FunctionCall{ fun, var_list, named, broadcast })
+    }
+
+    /// Reads an exec string, JSON, JSON Pointer, duration, size, or regex pattern passed as a literal to the built-in
+    /// fun, so a bad one is an error now instead of when the script runs. Only the built-ins are checked, as a user
+    /// function's params can have the same names. Exec strings are recorded for check_commands().
+    fn check_literal_argument(&mut self, fun_call: &Pair<Rule>, fun: &Function, param: &Variable, arg: &Variable) -> Result<(), ParseError> {
+        let literal = match self.string_literal(arg) {
+            Some(literal) => literal,
+            None => return Ok( () )
+        };
+
+        let text = &unquote(&literal);
+
+        match (fun.name.as_str(), param.name.as_str()) {
+            ("run" | "capture" | "retry", "exec") => match exec_string::split(text) {
+                Ok(args) => self.commands.extend(args.into_iter().next().map(|program| (program, Location::from_pair(fun_call)))),
+                Err(e) => return Err(ParseError::new(fun_call.clone(), ErrorKind::InvalidCommand, format!("Cannot split the command {} into arguments: {}", literal, e)))
+            },
+            ("json_parse" | "json_get", "json") => if let Err(e) = serde_json::from_str::<serde_json::Value>(text) {
+                return Err(ParseError::new(fun_call.clone(), ErrorKind::InvalidJson, format!("Cannot parse {} as JSON: {} of the string", literal, e)));
+            },
+            ("json_get", "pointer") if !text.is_empty() && !text.starts_with('/') => {
+                return Err(ParseError::new(fun_call.clone(), ErrorKind::InvalidJson, format!("Cannot use {} as a JSON Pointer, it must be empty or start with /", literal)));
+            },
+            ("parse_duration", "duration") => if let Err(e) = units::parse_duration(text) {
+                return Err(ParseError::new(fun_call.clone(), ErrorKind::InvalidUnit, format!("Cannot read the duration {}: {}", literal, e)));
+            },
+            ("parse_size", "size") => if let Err(e) = units::parse_size(text) {
+                return Err(ParseError::new(fun_call.clone(), ErrorKind::InvalidUnit, format!("Cannot read the size {}: {}", literal, e)));
+            },
+            ("pipe_match" | "pipe_match_all_groups", "pattern") => if let Err(e) = regex::Regex::new(text) {
+                return Err(ParseError::new(fun_call.clone(), ErrorKind::InvalidPattern, format!("Cannot compile the pattern {}: {}", literal, e)));
+            },
+            _ => { }
+        }
+
+        Ok( () )
+    }
+
+    /// Moves the named arguments into the position of their params, after the positional ones, filling in
+    /// any params between them from the defaults. Returns the params given by name, in the order they were
+    /// written, followed by the defaults filled in.
+    fn place_named_args(&mut self, fun_call: &Pair<Rule>, fun: &Function, var_list: &mut Vec<Variable>, named: Vec<(&str, usize, Variable)>) -> Result<Vec<usize>, ParseError> {
+        // a variadic param is never named, and is left empty when any argument is
+        let fixed = fun.params.len() - usize::from(fun.is_variadic);
+
+        if var_list.len() > fixed {
+            return Err(ParseError::new(fun_call.clone(), ErrorKind::ArgumentCount, format!("{} takes {} arguments, but {} were given", fun.name, fixed, var_list.len() + named.len())));
+        }
+
+        let required = fixed - fun.defaults.len();
+        let mut order = named.iter().map(|(_, i, _)| *i).collect::<Vec<_>>();
+        let mut slots = var_list.drain(..).map(Some).collect::<Vec<_>>();
+
+        slots.resize(fixed, None);
+
+        for (_, i, value) in named {
+            slots[i] = Some(value);
+        }
+
+        for (i, slot) in slots.iter_mut().enumerate() {
+            if slot.is_some() {
+                continue;
             }
+
+            if i < required {
+                return Err(ParseError::new(fun_call.clone(), ErrorKind::ArgumentCount, format!("{} needs an argument for {}", fun.name, fun.params[i].display_decl())));
+            }
+
+            *slot = Some(self.default_argument(fun_call, fun, i)?);
+            order.push(i);
+        }
+
+        var_list.extend(slots.into_iter().map(Option::unwrap));
+
+        Ok(order)
+    }
+
+    /// Lowers the default value of the i'th param of fun where it is called, returning the variable holding it.
+    /// The default is parsed again from its source, so it reads the variables in scope at the call, and a default
+    /// that calls its own function, directly or through another default, is an error instead of expanding forever.
+    fn default_argument(&mut self, fun_call: &Pair<Rule>, fun: &Function, i: usize) -> Result<Variable, ParseError> {
+        let param = &fun.params[i];
+        let source = &fun.defaults[i + fun.defaults.len() - fun.params.len()];
+        let in_default = |e: ParseError| ParseError::new(fun_call.clone(), e.kind(), format!("In the default value {} of {}: {}", source, param.name, e.reason()));
+
+        if self.expanding.contains(&fun.name) {
+            return Err(ParseError::new(fun_call.clone(), ErrorKind::RecursiveDefault, format!("Cannot fill in the default value {} of {}, as {} is called again while filling in its own defaults", source, param.name, fun.name)));
+        }
+
+        let expression = match SSSParser::parse(Rule::expression, source) {
+            Ok(mut pairs) => pairs.next().unwrap(),
+            Err(e) => return Err(in_default(ParseError::from_pest(e)))
+        };
+
+        let start = self.code.len();
+
+        self.expanding.push(fun.name.clone());
+        let value = self.process_argument(expression);
+        self.expanding.pop();
+
+        let value = value.map_err(in_default)?;
+
+        // the code computing the value is reported as part of the call
+        for e in self.code[start..].iter_mut() {
+            *e.location_mut() = Location::from_pair(fun_call);
         }
 
-        Ok(FunctionCall{ fun, var_list })
+        if !value.var_def.is_compatible_with(&param.var_def) {
+            return Err(ParseError::new(fun_call.clone(), ErrorKind::TypeMismatch, format!("Default value {} of {} is a {}, but {} is a {}", source, param.name, value.var_def, param.name, param.var_def)));
+        }
+
+        Ok(value)
+    }
+
+    /// Returns the string, with its quotes, when var is a temp holding a string literal
+    fn string_literal(&self, var: &Variable) -> Option<String> {
+        if !var.is_temp() {
+            return None;
+        }
+
+        self.code.iter().rev().find_map(|e| match e {
+            Expression::Assignment(_, Assignment { lhs, rhs: RightHandSide::Term(Term::String(s)) }) if lhs.name == var.name => Some(s.clone()),
+            _ => None
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::SSSParser;
+    use pest::Parser;
+
+    fn parse(source: &str) -> Result<Script, Vec<ParseError>> {
+        Script::new(SSSParser::parse(Rule::script, source).unwrap().next().unwrap())
+    }
+
+    /// The index in the top-level code of the first call to name
+    fn call_index(script: &Script, name: &str) -> usize {
+        script.code.iter().position(|e| match e {
+            Expression::FunctionCall(_, fc) | Expression::Assignment(_, Assignment { rhs: RightHandSide::FunctionCall(fc), .. }) => fc.fun.name == name,
+            _ => false
+        }).unwrap()
+    }
+
+    const INLINE: &str = "fun double(n:num) -> num {\n    var twice:num = n * 2;\n}\n\
+                          fun bump(n:num) -> num {\n    n = n + 1;\n}\n\
+                          fun nothing() -> num {\n    print_args(\"x\");\n}\n\
+                          fun sum(nums:num...) -> num {\n    var total:num = 0;\n}\n\
+                          var x:num = 3;\nvar twice:num = double(x);\nvar bumped:num = bump(x);\n";
+
+    /// The top-level code as three-address code
+    fn top_level_ir(script: &Script) -> String {
+        let mut emitter = IrEmitter::new();
+
+        emitter.emit_code(&script.code);
+        emitter.finish()
+    }
+
+    #[test]
+    fn inline_function_renames_params_and_locals() {
+        let mut script = parse(INLINE).unwrap();
+        let index = call_index(&script, "double");
+
+        script.inline_function(index, "double").unwrap();
+
+        // the param reads the argument, and the local is renamed, as twice is already a global
+        assert_eq!(top_level_ir(&script), "_t5 = 3\nx = _t5\n_t8 = 2\ndouble_twice = x * _t8\ntwice = double_twice\n_t7 = bump(x)\nbumped = _t7\n");
+        assert!(script.variables.contains_key("double_twice"));
+    }
+
+    #[test]
+    fn inline_function_copies_assigned_params() {
+        let mut script = parse(INLINE).unwrap();
+        let index = call_index(&script, "bump");
+
+        script.inline_function(index, "bump").unwrap();
+
+        // bump assigns n, so it gets a copy of x, and x keeps its value
+        assert!(top_level_ir(&script).ends_with("bump_n = x\n_t8 = 1\nbump_n = bump_n + _t8\nbumped = bump_n\n"), "{}", top_level_ir(&script));
+    }
+
+    #[test]
+    fn inline_function_errors() {
+        let mut script = parse(&format!("{}var v:num = nothing();\nvar s:num = sum(1, 2);\n", INLINE)).unwrap();
+        let nothing = call_index(&script, "nothing");
+        let sum = call_index(&script, "sum");
+        let double = call_index(&script, "double");
+
+        assert_eq!(script.inline_function(nothing, "nothing"), Err(RefactorError::NoValue(String::from("nothing"))));
+        assert_eq!(script.inline_function(sum, "sum"), Err(RefactorError::VariadicFunction(String::from("sum"))));
+        assert_eq!(script.inline_function(double, "bump"), Err(RefactorError::NotACall(double, String::from("bump"))));
+        assert_eq!(script.inline_function(0, "double"), Err(RefactorError::NotACall(0, String::from("double"))));
+    }
+
+    const EXTRACT: &str = "var a:num = 1;\nvar b:num = 2;\nvar c:num = a + b;\nvar d:num = c * 2;\nvar e:num = c + d;\nvar f:num = e +\n    1;\n";
+
+    #[test]
+    fn extract_function_reads_params_and_returns_the_output() {
+        let mut script = parse(EXTRACT).unwrap();
+
+        script.extract_function(4, 4, "times").unwrap();
+
+        let fun = &script.user_functions["times"];
+
+        assert_eq!(fun.params.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), vec!["c"]);
+        assert_eq!(fun.ret_type.as_ref().map(|t| t.to_string()), Some(String::from("num")));
+        assert!(top_level_ir(&script).contains("\nd = times(c)\n"), "{}", top_level_ir(&script));
+        assert!(script.emit_ir().starts_with("fun times(c:num) -> num {\n    _t3 = 2\n    d = c * _t3\n}\n"), "{}", script.emit_ir());
+    }
+
+    #[test]
+    fn extract_function_errors() {
+        let mut script = parse(EXTRACT).unwrap();
+
+        assert_eq!(script.extract_function(3, 4, "calc"), Err(RefactorError::MultipleOutputs(vec![String::from("c"), String::from("d")])));
+        assert_eq!(script.extract_function(7, 7, "calc"), Err(RefactorError::AmbiguousSpan(6)));
+        assert_eq!(script.extract_function(3, 3, "a"), Err(RefactorError::NameTaken(String::from("a"))));
+        assert_eq!(script.extract_function(9, 9, "calc"), Err(RefactorError::EmptyRange(9, 9)));
+    }
+
+    #[test]
+    fn looks_up_variables_and_functions() {
+        let script = parse(INLINE).unwrap();
+
+        assert_eq!(script.get_variable("x").map(|v| v.var_def.to_string()), Some(String::from("num")));
+        assert!(script.has_variable("_t5"));
+        assert!(!script.has_variable("n"));
+        assert_eq!(script.get_function("double").map(|f| f.params.len()), Some(1));
+        assert!(script.has_function("double"));
+        assert!(script.has_function("run"));
+        assert!(!script.has_function("x"));
+    }
+
+    #[test]
+    fn finds_declarations() {
+        let script = parse(INLINE).unwrap();
+
+        assert_eq!(script.find_declaration("double"), Some((1, 5, 6)));
+        assert_eq!(script.find_declaration("bumped"), Some((15, 5, 6)));
+        assert_eq!(script.find_declaration("_t5"), None);
+        assert_eq!(script.find_declaration("run"), None);
+    }
+
+    const TWO_FUNCTIONS: &str = "fun first(a:num) {\n    var b:num = a;\n}\nfun second(s:str) {\n    var t:str = s;\n}\nvar x:num = 1;\nfirst(x);\n";
+
+    #[test]
+    fn finds_the_function_by_line() {
+        let script = parse(TWO_FUNCTIONS).unwrap();
+
+        assert_eq!(script.find_function_by_line(2).map(|f| f.name.as_str()), Some("first"));
+        assert_eq!(script.find_function_by_line(5).map(|f| f.name.as_str()), Some("second"));
+        assert!(script.find_function_by_line(8).is_none());
+    }
+
+    #[test]
+    fn finds_the_expression_at_line() {
+        let script = parse(TWO_FUNCTIONS).unwrap();
+
+        assert!(matches!(script.find_expression_at_line(5), Some(Expression::Assignment(_, a)) if a.lhs.name == "t"));
+        assert!(matches!(script.find_expression_at_line(8), Some(Expression::FunctionCall(_, fc)) if fc.fun.name == "first"));
+        assert!(script.find_expression_at_line(3).is_none());
+    }
+
+    #[test]
+    fn counts_all_expressions() {
+        let mut script = parse(&format!("{}var y:num = x ? 2 : 3;\n", TWO_FUNCTIONS)).unwrap();
+
+        // _t1 = 1, x = _t1, first(x), the conditional with two expressions in each branch, and y = _t4
+        // at the top level, then b = a and t = s in the functions
+        assert_eq!(script.all_expressions().count(), 11);
+        assert_eq!(script.all_expressions_mut().count(), 7);
+        assert_eq!(script.all_assignments().count(), 9);
+        assert_eq!(script.all_function_calls().map(|fc| fc.fun.name.as_str()).collect::<Vec<_>>(), vec!["first"]);
+    }
+
+    #[test]
+    fn reset_temps_renumbers_from_one() {
+        let mut script = parse("var c:str = \"x\";\nvar a:num = 1 + 2;\n").unwrap();
+
+        script.inline_single_use_temps();
+        assert_eq!(script.emit_ir(), "c = \"x\"\n_t2 = 1\n_t3 = 2\na = _t2 + _t3\n");
+
+        // _t1 was inlined away, so it is dropped rather than renumbered
+        script.reset_temps();
+        assert_eq!(script.emit_ir(), "c = \"x\"\n_t1 = 1\n_t2 = 2\na = _t1 + _t2\n");
+
+        let mut temps = script.variables.keys().filter(|name| name.starts_with("_t")).collect::<Vec<_>>();
+
+        temps.sort();
+        assert_eq!(temps, vec!["_t1", "_t2"]);
+        assert_eq!(script.generate_temp(&VarDef::from_type(&VarType::Number)).name, "_t3");
+    }
+
+    #[test]
+    fn fresh_clones_share_nothing() {
+        let script = parse(TWO_FUNCTIONS).unwrap();
+        let ir = script.emit_ir();
+        let mut clone = script.fresh_clone();
+
+        assert_eq!(clone.emit_ir(), ir);
+
+        clone.code.clear();
+        clone.variables.remove("x");
+        clone.user_functions.get_mut("first").unwrap().code.clear();
+
+        assert_eq!(script.emit_ir(), ir);
+        assert!(script.variables.contains_key("x"));
+        assert_eq!(script.user_functions["first"].code.len(), 1);
+    }
+
+    #[test]
+    fn inlining_lowers_the_temp_count() {
+        let mut script = parse("fun greet() {\n    print_args(\"hi\");\n}\nvar c:str = \"x\";\nvar a:num = 1 + 2;\n").unwrap();
+
+        // "hi" in greet, then "x", 1 and 2 at the top level
+        assert_eq!(script.temp_variable_count(), 4);
+
+        script.inline_single_use_temps();
+
+        // only "x" is folded, into c = "x", as the arguments and operands of the others must be variables
+        assert_eq!(script.temp_variable_count(), 3);
+
+        let mut names = script.user_variables().into_keys().collect::<Vec<_>>();
+
+        names.sort();
+        assert_eq!(names, vec!["a", "c"]);
+    }
+
+    #[test]
+    fn functions_cannot_call_themselves() {
+        // so inlining a function never leaves a call to it behind
+        let errors = parse("fun again(n:num) -> num {\n    var m:num = again(n);\n}\n").err().unwrap();
+
+        assert_eq!(errors[0].kind(), ErrorKind::UnknownFunction, "{}", errors[0]);
     }
 }
@@ -0,0 +1,109 @@
+use pest::Parser;
+
+#[cfg(test)]
+use std::fs;
+
+use crate::{Rule, SSSParser};
+#[cfg(test)]
+use crate::expression::{Variable, VarDef};
+use crate::parse_error::ParseError;
+use crate::script::Script;
+use crate::builtins::BuiltinRegistry;
+
+/// Configures the built-in functions available to a script before parsing it
+///
+/// ```ignore
+/// let script = ScriptBuilder::new()
+///     .add_builtin("notify", vec![msg], None)
+///     .disable_builtin("run")
+///     .parse_file("script.sss")
+///     .build()?;
+/// ```
+pub struct ScriptBuilder {
+    builtins: BuiltinRegistry,
+    source: Result<String, ParseError>,
+    file: Option<String>
+}
+
+impl ScriptBuilder {
+    /// Starts with the standard built-ins and an empty script
+    pub fn new() -> ScriptBuilder {
+        ScriptBuilder { builtins: BuiltinRegistry::standard(), source: Ok(String::new()), file: None }
+    }
+
+    /// Adds a host-provided built-in function, replacing any existing built-in with the same name
+    #[cfg(test)]
+    pub fn add_builtin(mut self, name: &str, params: Vec<Variable>, ret_type: Option<VarDef>) -> ScriptBuilder {
+        self.builtins.register(name, params, ret_type);
+        self
+    }
+
+    /// Removes a built-in function, so calling it is an unknown function error
+    #[cfg(test)]
+    pub fn disable_builtin(mut self, name: &str) -> ScriptBuilder {
+        self.builtins.remove(name);
+        self
+    }
+
+    /// Uses source as the script, which is not read from any file
+    pub fn parse_str(mut self, source: &str) -> ScriptBuilder {
+        self.source = Ok(String::from(source));
+        self.file = None;
+        self
+    }
+
+    /// Reads the script from a file, any error reading it is reported by build()
+    #[cfg(test)]
+    pub fn parse_file(mut self, path: &str) -> ScriptBuilder {
+        self.source = fs::read_to_string(path).map_err(|e| ParseError::from_io(path, e));
+        self.file = Some(String::from(path));
+        self
+    }
+
+    /// Parses the script with the configured built-ins, returning the error reading it, the syntax error, or every error in its calls
+    pub fn build(self) -> Result<Script, Vec<ParseError>> {
+        let source = self.source.map_err(|e| vec![e])?;
+
+        let pairs = SSSParser::parse(Rule::script, &source)
+            .map_err(|e| vec![ParseError::from_pest_in(&source, e)])?
+            .next().unwrap();
+
+        let mut script = Script::with_builtins(pairs, self.builtins)?;
+
+        if let Some(file) = &self.file {
+            script.set_file(file);
+        }
+
+        Ok(script)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::expression::VarType;
+    use crate::parse_error::ErrorKind;
+
+    #[test]
+    fn added_builtins_can_be_called() {
+        let msg = Variable::from_def("msg", VarDef::from_type(&VarType::String));
+        let script = ScriptBuilder::new().add_builtin("notify", vec![msg], None).parse_str("notify(\"done\");").build().unwrap();
+
+        assert_eq!(script.emit_ir(), "_t1 = \"done\"\nnotify(_t1)\n");
+    }
+
+    #[test]
+    fn disabled_builtins_are_unknown() {
+        let errors = ScriptBuilder::new().disable_builtin("run").parse_str("run(\"ls\");").build().err().unwrap();
+
+        assert_eq!(errors.iter().map(|e| e.kind()).collect::<Vec<_>>(), vec![ErrorKind::UnknownFunction]);
+    }
+
+    #[test]
+    fn build_reports_a_file_that_cannot_be_read() {
+        let errors = ScriptBuilder::new().parse_file("tests/missing.sss").build().err().unwrap();
+
+        assert_eq!(errors.iter().map(|e| e.kind()).collect::<Vec<_>>(), vec![ErrorKind::Io]);
+    }
+}
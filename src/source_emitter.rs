@@ -0,0 +1,230 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::diagnostics::end_of;
+use crate::expression::*;
+
+/// Renders lowered code back into source that parses to the same code.
+/// Temps can't be written in source, so each one is folded back into the
+/// expression that reads it, the way it was written before it was lowered:
+///
+/// ```text
+/// _t1 = "a"
+/// _t2 = run(p, _t1)     =>     var out:pipe[] = run(p, "a");
+/// out = _t2
+/// ```
+pub struct SourceEmitter {
+    output: String,
+    indent: usize
+}
+
+impl SourceEmitter {
+    pub fn new() -> SourceEmitter {
+        SourceEmitter { output: String::new(), indent: 0 }
+    }
+
+    pub fn emit_function(&mut self, fun: &Function) {
+        let params = fun.params.iter().map(|p| p.name.clone()).collect();
+
+        for line in fun.docstring.iter().flat_map(|doc| doc.lines()) {
+            self.emit_line(format!("/// {}", line).trim_end());
+        }
+
+        self.emit_line(&format!("{} {{", fun.display_header()));
+        self.indent += 1;
+        self.emit_code(&fun.code, params);
+        self.indent -= 1;
+        self.emit_line("}");
+    }
+
+    /// Emits an entry point, which can assign the globals without declaring them
+    pub fn emit_entry_point(&mut self, name: &str, code: &[Expression], globals: HashSet<String>) {
+        self.emit_line(&format!("@{} {{", name));
+        self.indent += 1;
+        self.emit_code(code, globals);
+        self.indent -= 1;
+        self.emit_line("}");
+    }
+
+    /// Emits a statement for each assignment to a variable and each call, declaring a variable
+    /// the first time it is assigned unless it is in declared. Returns the declared variables.
+    pub fn emit_code(&mut self, code: &[Expression], declared: HashSet<String>) -> HashSet<String> {
+        self.emit(code, declared, HashMap::new(), Vec::new()).0
+    }
+
+    /// Emits code like emit_code(), starting with the source of the temps already folded, and also folding
+    /// the temps in read_after, which are read after the code. Returns the declared variables and the temps.
+    fn emit(&mut self, code: &[Expression], mut declared: HashSet<String>, mut temps: HashMap<String, String>, read_after: Vec<&Variable>) -> (HashSet<String>, HashMap<String, String>) {
+        let reads = code.iter().flat_map(|e| e.operands()).chain(read_after).map(|v| v.name.clone()).collect::<HashSet<_>>();
+
+        // "x <- cmd" lowers to capture(cmd), the "stdout" of that, and then str_trim_newlines, and is written back the same way.
+        // These are the source of cmd for the temps holding the result of capture, and the stdout taken from it.
+        let mut captures = HashMap::<String, String>::new();
+        let mut stdouts = HashMap::<String, String>::new();
+
+        // the statements of each block expression, by the index of the temp its value is assigned to,
+        // and the variables they declare, which go out of scope at the end of the block
+        let mut blocks = HashMap::<usize, (Vec<String>, Vec<String>)>::new();
+
+        for (i, e) in code.iter().enumerate() {
+            let block = enclosing_block(code, i);
+            let statement = |emitter: &mut SourceEmitter, blocks: &mut HashMap<usize, (Vec<String>, Vec<String>)>, line: String| match block {
+                Some(j) => blocks.entry(j).or_default().0.push(line),
+                None => emitter.emit_line(&line)
+            };
+
+            match e {
+                Expression::Assignment(_, a) if is_block(e) => {
+                    let (lines, locals) = blocks.remove(&i).unwrap_or_default();
+                    let value = format!("{{ {}{} }}", lines.iter().map(|l| format!("{} ", l)).collect::<String>(), render(&a.rhs, &temps));
+
+                    for local in locals {
+                        declared.remove(&local);
+                    }
+
+                    if reads.contains(&a.lhs.name) {
+                        temps.insert(a.lhs.name.clone(), value);
+                    } else {
+                        statement(self, &mut blocks, format!("{};", value));
+                    }
+                },
+                Expression::Assignment(_, a) if a.lhs.is_temp() => {
+                    let value = render(&a.rhs, &temps);
+
+                    match &a.rhs {
+                        RightHandSide::FunctionCall(fc) if fc.fun.name == "capture" && fc.var_list.len() == 1 => {
+                            captures.insert(a.lhs.name.clone(), operand(&fc.var_list[0], &temps));
+                        },
+                        RightHandSide::Index(map, key) if temps.get(&key.name).map(|k| k.as_str()) == Some("\"stdout\"") => {
+                            if let Some(cmd) = captures.get(&map.name) {
+                                stdouts.insert(a.lhs.name.clone(), cmd.clone());
+                            }
+                        },
+                        _ => { }
+                    }
+
+                    // operations and copies only end up in a temp when they are in parentheses, or are the left
+                    // side of another operator where parentheses lower the same way. Unread operations are
+                    // statements like "a + 1;", which are assigned to a temp without any parentheses.
+                    let value = match a.rhs {
+                        RightHandSide::Operation(..) if !reads.contains(&a.lhs.name) => value,
+                        RightHandSide::Operation(..) | RightHandSide::Variable(_) => format!("({})", value),
+                        _ => value
+                    };
+
+                    if reads.contains(&a.lhs.name) {
+                        temps.insert(a.lhs.name.clone(), value);
+                    } else {
+                        statement(self, &mut blocks, format!("{};", value)); // an expression used as a statement
+                    }
+                },
+                Expression::Assignment(_, a) => {
+                    let captured = match &a.rhs {
+                        RightHandSide::FunctionCall(fc) if fc.fun.name == "str_trim_newlines" && fc.var_list.len() == 1 => stdouts.get(&fc.var_list[0].name),
+                        _ => None
+                    };
+
+                    let (op, value) = match captured {
+                        Some(cmd) => ("<-", cmd.clone()),
+                        None => ("=", render(&a.rhs, &temps))
+                    };
+
+                    if declared.insert(a.lhs.name.clone()) {
+                        statement(self, &mut blocks, format!("{} {} {} {};", a.lhs.keyword(), a.lhs.display_decl(), op, value));
+
+                        if let Some(j) = block {
+                            blocks.entry(j).or_default().1.push(a.lhs.name.clone());
+                        }
+                    } else {
+                        statement(self, &mut blocks, format!("{} {} {};", a.lhs.name, op, value));
+                    }
+                },
+                Expression::IndexAssignment(_, ia) => {
+                    statement(self, &mut blocks, format!("{}[{}] = {};", ia.map.name, operand(&ia.key, &temps), operand(&ia.value, &temps)));
+                },
+                Expression::FunctionCall(_, fc) => {
+                    let call = fc.to_source_with(&|v| operand(v, &temps));
+
+                    statement(self, &mut blocks, format!("{};", call));
+                },
+                Expression::Conditional(_, c) => {
+                    let then = branch(&c.then_code, &temps, &declared);
+                    let other = branch(&c.else_code, &temps, &declared);
+                    let value = format!("{} ? {} : {}", operand(&c.cond, &temps), then, other);
+
+                    if reads.contains(&c.result.name) {
+                        temps.insert(c.result.name.clone(), value);
+                    } else {
+                        statement(self, &mut blocks, format!("{};", value));
+                    }
+                }
+            }
+        }
+
+        (declared, temps)
+    }
+
+    /// Returns everything emitted so far
+    pub fn finish(self) -> String {
+        self.output
+    }
+
+    fn emit_line(&mut self, line: &str) {
+        for _ in 0..self.indent {
+            self.output.push_str("    ");
+        }
+
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+}
+
+/// True for the temp a block expression's value is assigned to, which has the source of the whole block.
+/// Map literals start with a brace too, but are never assigned from anything but a map.
+fn is_block(e: &Expression) -> bool {
+    match e {
+        Expression::Assignment(loc, a) => a.lhs.is_temp() && loc.text.starts_with('{') && !matches!(a.rhs, RightHandSide::MapLiteral(..)),
+        _ => false
+    }
+}
+
+/// The index of the innermost block expression code[i] was written in, so it is written back there,
+/// rather than ahead of the statement the block is part of. A block's value is assigned after the
+/// code of the block, so the first block after code[i] that holds its source is the innermost.
+fn enclosing_block(code: &[Expression], i: usize) -> Option<usize> {
+    let span = |loc: &Location| ((loc.line, loc.col), end_of(loc.line, loc.col, &loc.text));
+    let (start, end) = span(code[i].location());
+
+    code.iter().enumerate().skip(i + 1).find(|(_, e)| {
+        let (block_start, block_end) = span(e.location());
+
+        is_block(e) && e.location().file == code[i].location().file && block_start <= start && end <= block_end && (block_start, block_end) != (start, end)
+    }).map(|(j, _)| j)
+}
+
+/// Renders the code of a branch of a conditional as the source of the value it assigns to the result last.
+/// Code that is not folded into the value, which an expression does not lower to, is written as a block.
+fn branch(code: &[Expression], temps: &HashMap<String, String>, declared: &HashSet<String>) -> String {
+    let (rest, value) = match code.split_last() {
+        Some((Expression::Assignment(_, a), rest)) => (rest, &a.rhs),
+        _ => unreachable!("A branch ends by assigning its value")
+    };
+
+    let mut emitter = SourceEmitter::new();
+    let (_, temps) = emitter.emit(rest, declared.clone(), temps.clone(), value.operands());
+    let value = render(value, &temps);
+
+    match emitter.finish() {
+        lines if lines.is_empty() => value,
+        lines => format!("{{ {} {} }}", lines.lines().collect::<Vec<_>>().join(" "), value)
+    }
+}
+
+/// Renders a variable read as an operand, a temp as the source of its value
+fn operand(v: &Variable, temps: &HashMap<String, String>) -> String {
+    temps.get(&v.name).cloned().unwrap_or_else(|| v.name.clone())
+}
+
+/// Renders a right-hand-side with each temp it reads replaced by the source of the temp's value
+fn render(rhs: &RightHandSide, temps: &HashMap<String, String>) -> String {
+    rhs.to_source_with(&|v| operand(v, temps))
+}
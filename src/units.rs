@@ -0,0 +1,91 @@
+use std::fmt;
+
+/// Why a duration or a size could not be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum UnitError {
+    Empty,
+    InvalidNumber(String), // the text where a number was expected
+    MissingUnit(String),   // the number that has no unit after it
+    UnknownUnit(String)
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnitError::Empty => write!(f, "it is empty"),
+            UnitError::InvalidNumber(s) => write!(f, "expected a number at {}", s),
+            UnitError::MissingUnit(n) => write!(f, "{} has no unit", n),
+            UnitError::UnknownUnit(u) => write!(f, "unknown unit {}", u)
+        }
+    }
+}
+
+/// The units of a duration, in seconds
+const DURATION_UNITS: &[(&str, f64)] = &[("ms", 0.001), ("s", 1.0), ("m", 60.0), ("h", 3600.0), ("d", 86400.0), ("w", 604800.0)];
+
+/// The units of a size, in bytes: decimal multiples of 1000, and binary ones of 1024
+const SIZE_UNITS: &[(&str, f64)] = &[
+    ("B", 1.0),
+    ("KB", 1e3), ("MB", 1e6), ("GB", 1e9), ("TB", 1e12), ("PB", 1e15),
+    ("KiB", 1024.0), ("MiB", 1048576.0), ("GiB", 1073741824.0), ("TiB", 1099511627776.0), ("PiB", 1125899906842624.0)
+];
+
+/// Splits the number off the front of s, returning it and the rest of s
+fn leading_number(s: &str) -> Result<(f64, &str), UnitError> {
+    let end = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+
+    match s[..end].parse::<f64>() {
+        Ok(n) => Ok((n, &s[end..])),
+        Err(_) => Err(UnitError::InvalidNumber(String::from(s)))
+    }
+}
+
+/// Reads a duration into seconds. It is one or more numbers, each followed by a unit of
+/// ms, s, m, h, d, or w, like "500ms", "2h", or "1d2h30m". Numbers can have a fraction, like "1.5h".
+pub fn parse_duration(s: &str) -> Result<f64, UnitError> {
+    if s.is_empty() {
+        return Err(UnitError::Empty);
+    }
+
+    let mut rest = s;
+    let mut secs = 0.0;
+
+    while !rest.is_empty() {
+        let (n, after) = leading_number(rest)?;
+        let unit_len = after.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(after.len());
+        let unit = &after[..unit_len];
+
+        if unit.is_empty() {
+            return Err(UnitError::MissingUnit(String::from(&rest[..rest.len() - after.len()])));
+        }
+
+        match DURATION_UNITS.iter().find(|(name, _)| *name == unit) {
+            Some((_, scale)) => secs += n * scale,
+            None => return Err(UnitError::UnknownUnit(String::from(unit)))
+        }
+
+        rest = &after[unit_len..];
+    }
+
+    Ok(secs)
+}
+
+/// Reads a size into bytes. It is a number, optionally followed by a unit, like "512", "10GiB", or
+/// "1.5 MB". KB, MB, and so on are powers of 1000, while KiB, MiB, and so on are powers of 1024.
+pub fn parse_size(s: &str) -> Result<f64, UnitError> {
+    if s.is_empty() {
+        return Err(UnitError::Empty);
+    }
+
+    let (n, unit) = leading_number(s)?;
+    let unit = unit.trim_start();
+
+    if unit.is_empty() {
+        return Ok(n);
+    }
+
+    match SIZE_UNITS.iter().find(|(name, _)| *name == unit) {
+        Some((_, scale)) => Ok(n * scale),
+        None => Err(UnitError::UnknownUnit(String::from(unit)))
+    }
+}
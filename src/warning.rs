@@ -0,0 +1,77 @@
+use super::Rule;
+use pest::iterators::Pair;
+use serde::{Serialize, Deserialize};
+
+use std::fmt;
+
+use crate::diagnostics::{Diagnostic, end_of};
+use crate::expression::Location;
+
+/// The kind of a Warning, each with a short stable code for tooling
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum WarningKind {
+    ShadowedParameter,
+    NoEffect,
+    UnusedResult,
+    MissingCommand
+}
+
+impl WarningKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            WarningKind::ShadowedParameter => "shadowed-parameter",
+            WarningKind::NoEffect => "no-effect",
+            WarningKind::UnusedResult => "unused-result",
+            WarningKind::MissingCommand => "missing-command"
+        }
+    }
+}
+
+/// A non-fatal diagnostic produced while constructing a Script
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Warning {
+    kind: WarningKind,
+    source: String,
+    line: usize,
+    col: usize,
+    end_line: usize,
+    end_col: usize,
+    reason: String
+}
+
+impl Warning {
+    pub fn new(rule: Pair<Rule>, kind: WarningKind, reason: String) -> Warning {
+        let source = String::from(rule.as_str());
+        let (line, col) = rule.as_span().start_pos().line_col();
+        let (end_line, end_col) = rule.as_span().end_pos().line_col();
+
+        Warning { kind, source, line, col, end_line, end_col, reason }
+    }
+
+    /// Constructs a warning about the source of an expression, after the rules it came from are gone
+    pub fn from_location(loc: &Location, kind: WarningKind, reason: String) -> Warning {
+        let (end_line, end_col) = end_of(loc.line, loc.col, &loc.text);
+
+        Warning { kind, source: loc.text.clone(), line: loc.line, col: loc.col, end_line, end_col, reason }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic<'_> {
+        Diagnostic {
+            line: self.line,
+            col: self.col,
+            end_line: self.end_line,
+            end_col: self.end_col,
+            severity: "warning",
+            code: self.kind.code(),
+            source: &self.source,
+            message: &self.reason
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.line, self.source)?;
+        writeln!(f, "warning: {}", self.reason)
+    }
+}
@@ -0,0 +1,383 @@
+//! Tests of the command line: the happy path of each subcommand, and the errors it reports
+
+use assert_cmd::Command;
+
+fn sss() -> Command {
+    Command::cargo_bin("sss").unwrap()
+}
+
+#[test]
+fn run_is_the_default() {
+    sss().arg("tests/cases/declaration.sss").assert().success();
+    sss().args(["run", "tests/cases/declaration.sss", "--", "a", "b"]).assert().success();
+}
+
+#[test]
+fn run_reports_parse_errors() {
+    sss().args(["run", "tests/cases/redeclaration.err.sss"]).assert().failure().code(1);
+}
+
+#[test]
+fn unknown_flags_are_errors() {
+    let output = sss().args(["--bogus", "tests/cases/declaration.sss"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--bogus"));
+}
+
+#[test]
+fn check_prints_warnings() {
+    let output = sss().args(["check", "--diagnostics=json", "tests/shadow.sss"]).assert().success().get_output().clone();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let warnings = stdout.lines().filter(|l| l.contains("\"severity\":\"warning\"")).collect::<Vec<_>>();
+
+    assert_eq!(warnings.len(), 1, "{}", stdout);
+    assert!(warnings[0].contains("\"message\":\"Parameter x of function f shadows global variable x\""), "{}", stdout);
+
+    sss().args(["check", "--no-warnings", "tests/shadow.sss"]).assert().success().stderr("");
+}
+
+#[test]
+fn check_warns_about_discarded_statements() {
+    let output = sss().args(["check", "--diagnostics=json", "tests/statements.sss"]).assert().success().get_output().clone();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let warnings = stdout.lines().collect::<Vec<_>>();
+
+    // the chained method call on line 5 runs commands, so it has no warning
+    assert_eq!(warnings.len(), 2, "{}", stdout);
+    assert!(warnings[0].contains("\"line\":3,") && warnings[0].contains("\"message\":\"Result of `collect` is unused\""), "{}", stdout);
+    assert!(warnings[1].contains("\"line\":4,") && warnings[1].contains("\"message\":\"Statement has no effect\""), "{}", stdout);
+}
+
+#[test]
+fn check_prints_json_errors() {
+    let diagnostics = |args: &[&str]| -> Vec<serde_json::Value> {
+        let output = sss().args(args).assert().failure().get_output().clone();
+
+        String::from_utf8_lossy(&output.stdout).lines().map(|l| serde_json::from_str(l).unwrap()).collect()
+    };
+
+    let errors = diagnostics(&["check", "--diagnostics=json", "tests/cases/redeclaration.err.sss"]);
+
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["code"], "redeclaration");
+    assert_eq!((&errors[0]["line"], &errors[0]["column"], &errors[0]["end_line"], &errors[0]["end_column"]), (&2.into(), &1.into(), &2.into(), &16.into()));
+
+    // every call that is wrong is reported, not just the first
+    let calls = diagnostics(&["check", "--diagnostics=json", "tests/cases/call_errors.err.sss"]);
+
+    assert_eq!(calls.len(), 2);
+    assert_eq!((&calls[0]["code"], &calls[0]["line"]), (&"argument-count".into(), &2.into()));
+    assert_eq!((&calls[1]["code"], &calls[1]["line"]), (&"type-mismatch".into(), &3.into()));
+
+    // the warnings of a script that parses come before an error running it
+    let both = diagnostics(&["--diagnostics=json", "--entry", "missing", "tests/shadow.sss"]);
+
+    assert_eq!(both.len(), 2);
+    assert_eq!((&both[0]["severity"], &both[0]["code"]), (&"warning".into(), &"shadowed-parameter".into()));
+    assert_eq!((&both[0]["line"], &both[0]["column"], &both[0]["end_line"], &both[0]["end_column"]), (&3.into(), &7.into(), &3.into(), &12.into()));
+    assert_eq!((&both[1]["severity"], &both[1]["code"]), (&"error".into(), &"unknown-entry-point".into()));
+    assert_eq!((&both[1]["line"], &both[1]["column"]), (&0.into(), &0.into()));
+}
+
+#[test]
+fn check_requires_a_script() {
+    sss().arg("check").assert().failure();
+}
+
+#[test]
+fn ast_prints_the_script() {
+    let output = sss().args(["ast", "tests/cases/declaration.sss"]).assert().success().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("FUNCTIONS:"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("tests/cases/declaration.sss:1:1: a = "));
+}
+
+#[test]
+fn ast_leaves_temps_out_of_the_variables() {
+    let output = sss().args(["ast", "tests/cases/declaration.sss"]).assert().success().get_output().clone();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let variables = stdout.lines().find(|l| l.starts_with("VARIABLES:")).unwrap();
+
+    assert!(variables.contains("\"a\"") && !variables.contains("\"_t1\""));
+    assert!(stdout.contains("TEMPS: 3\n"));
+}
+
+#[test]
+fn ast_reports_a_missing_file() {
+    sss().args(["ast", "tests/cases/missing.sss"]).assert().failure();
+}
+
+#[test]
+fn emit_ir_prints_three_address_code() {
+    let expected = "\
+fun hello(world:str) {
+    _t1 = \"hello\"
+    a = _t1
+}
+_t2 = \"hello\"
+_t3 = run(_t2)
+_t4 = \"world\"
+run(_t3, _t4)
+";
+
+    sss().args(["--emit=ir", "tests/simple.sss"]).assert().success().stdout(expected);
+}
+
+#[test]
+fn repl_prints_each_statement() {
+    sss().arg("repl").write_stdin("var a:str = \"x\";\nvar b:str = a;\n").assert().success().stdout("> _t1 = \"x\"\na = _t1\n> b = a\n> ");
+}
+
+#[test]
+fn repl_drops_bad_statements() {
+    let output = sss().arg("repl").write_stdin("var a:num = 1;\nvar a:num = 2;\na = 3;\n").assert().success().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Redeclaration of a"));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("a = _t2"));
+}
+
+#[test]
+fn color_is_forced_both_ways() {
+    let colored = sss().args(["check", "--color=always", "tests/cases/redeclaration.err.sss"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&colored.stderr).contains("\x1b[31m^"));
+
+    sss().args(["check", "--color=never", "tests/cases/redeclaration.err.sss"]).assert().failure()
+        .stderr("2: var a:str = \"x\"\nRedeclaration of a:num, previously declared at line 1\n");
+}
+
+#[test]
+fn warnings_are_yellow() {
+    let colored = sss().args(["check", "--color=always", "tests/shadow.sss"]).assert().success().get_output().clone();
+    let plain = sss().args(["check", "--color=never", "tests/shadow.sss"]).assert().success().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&colored.stderr).contains("\x1b[33mwarning: "));
+    assert!(!String::from_utf8_lossy(&plain.stderr).contains('\x1b'));
+}
+
+#[test]
+fn precompiled_libraries_round_trip() {
+    let dir = std::env::temp_dir().join(format!("sss-precompile-{}", std::process::id()));
+    let script = dir.join("function.sss");
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("tests/cases/function.sss", &script).unwrap();
+
+    sss().arg("--precompile").arg(&script).assert().success();
+
+    // the library keeps the functions, but not the calls of the top-level code
+    sss().arg("--emit=ir").arg(dir.join("function.sssl")).assert().success()
+        .stdout("fun hello(world:str) -> num {\n    a = world\n}\n");
+
+    // and the code assigning its variables
+    std::fs::write(dir.join("settings.sss"), "var count:num = 3;\nrun(\"ls\");\n").unwrap();
+
+    sss().arg("--precompile").arg(dir.join("settings.sss")).assert().success();
+
+    let output = sss().args(["--emit=ir", "--inline-temps"]).arg(dir.join("settings.sssl")).assert().success().get_output().clone();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "count = 3\n");
+
+    std::fs::write(dir.join("bad.sssl"), b"not a library").unwrap();
+
+    let output = sss().args(["check", "--diagnostics=json"]).arg(dir.join("bad.sssl")).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"code\":\"invalid-library\""));
+
+    // a directory in the way of the library is reported, rather than a panic
+    std::fs::copy("tests/cases/function.sss", dir.join("blocked.sss")).unwrap();
+    std::fs::create_dir_all(dir.join("blocked.sssl")).unwrap();
+
+    let output = sss().args(["--precompile", "--diagnostics=json"]).arg(dir.join("blocked.sss")).assert().failure().code(1).get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"code\":\"io\""));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn entry_points_are_selected_by_name() {
+    sss().args(["--entry", "test_greet", "tests/cases/multientry.sss"]).assert().success();
+    sss().args(["--entry", "missing", "--color=never", "tests/cases/multientry.sss"]).assert().failure()
+        .stderr("0: missing\nUnknown entry point @missing\n");
+}
+
+#[test]
+fn test_mode_lists_the_test_blocks() {
+    // the blocks are not run yet, so none of them pass
+    sss().args(["--test", "tests/cases/multientry.sss"]).assert().success()
+        .stdout("running 2 tests\ntest @test_assign ... ignored, not run\ntest @test_greet ... ignored, not run\n\ntest result: ok. 0 passed; 0 failed; 2 ignored\n");
+}
+
+#[test]
+fn profiles_a_large_script() {
+    let path = std::env::temp_dir().join(format!("sss-large-{}.sss", std::process::id()));
+    let mut source = String::new();
+
+    for i in 0..1000 {
+        source.push_str(&format!("var v{}:str = \"value\" + \"{}\";\n", i, i));
+    }
+
+    std::fs::write(&path, source).unwrap();
+
+    let output = sss().arg("--profile-parse").arg(&path).assert().success().get_output().clone();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.starts_with("parse: ") && !stdout.contains("build_ast"), "{}", stdout);
+
+    std::fs::remove_file(&path).unwrap();
+
+    let output = sss().args(["--profile-build-ast", "tests/cases/declaration.sss"]).assert().success().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains(", build_ast: "));
+}
+
+#[test]
+fn define_substitutes_variables() {
+    let output = sss().args(["--emit=ir", "--define", "retries=5", "--define", "mode=\"fast\"", "tests/define.sss"]).assert().success().get_output().clone();
+    let ir = String::from_utf8_lossy(&output.stdout);
+
+    assert!(!ir.contains("retries"), "{}", ir);
+    assert!(ir.contains("mode = \"fast\"\nlabel = \"fast\"\nrun(mode)"), "{}", ir);
+}
+
+#[test]
+fn inline_temps_shortens_the_code() {
+    let ir = |args: &[&str]| String::from_utf8_lossy(&sss().args(args).assert().success().get_output().stdout).into_owned();
+    let plain = ir(&["--emit=ir", "tests/cases/declaration.sss"]);
+    let inlined = ir(&["--emit=ir", "--inline-temps", "tests/cases/declaration.sss"]);
+
+    assert!(inlined.lines().count() < plain.lines().count(), "{}", inlined);
+}
+
+#[test]
+fn define_reports_bad_values() {
+    let output = sss().args(["--define", "retries=\"x\"", "tests/define.sss"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot substitute a str for retries, a num"));
+
+    let output = sss().args(["--diagnostics=json", "--define", "nope=1", "tests/define.sss"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"code\":\"invalid-define\""));
+}
+
+#[test]
+fn define_substitutes_library_variables() {
+    let dir = std::env::temp_dir().join(format!("sss-define-{}", std::process::id()));
+    let script = dir.join("settings.sss");
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(&script, "var count:num = 3;\nvar copy:num = count;\n").unwrap();
+
+    sss().arg("--precompile").arg(&script).assert().success();
+
+    let output = sss().args(["--emit=ir", "--define", "count=4"]).arg(dir.join("settings.sssl")).assert().success().get_output().clone();
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "copy = 4\n");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn eval_runs_code_from_the_command_line() {
+    sss().args(["--eval", "var x:num = 2 + 3;"]).assert().success();
+    sss().args(["--eval", "var x:num = 2 + 3;", "--emit", "source"]).assert().success().stdout("var x:num = 2 + 3;\n");
+    sss().args(["--eval", "var a:str[] = args();", "--", "a", "b"]).assert().success();
+}
+
+#[test]
+fn eval_expr_prints_the_expression() {
+    sss().args(["--eval-expr", "\"a\" + \"b\"", "--emit", "source"]).assert().success().stdout("print_args((\"a\" + \"b\"));\n");
+
+    let output = sss().args(["--eval-expr", "2 + 3"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Cannot pass a num as part of args of print_args"));
+}
+
+#[test]
+fn eval_reports_errors_against_eval() {
+    let output = sss().args(["--eval", "var x:num = y;", "--diagnostics=json"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("<eval>"));
+
+    sss().args(["--eval", "var x:num = 1;", "tests/cases/declaration.sss"]).assert().failure();
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn http_builtins_are_registered_with_the_feature() {
+    sss().args(["--eval", "var body:str = http_get(\"http://localhost\"); var code:num = http_status(\"http://localhost\");"]).assert().success();
+}
+
+#[cfg(not(feature = "http"))]
+#[test]
+fn http_builtins_are_unknown_without_the_feature() {
+    let output = sss().args(["--eval", "var body:str = http_get(\"http://localhost\");", "--diagnostics=json"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"code\":\"unknown-function\""));
+}
+
+#[cfg(feature = "hashing")]
+#[test]
+fn hashing_builtins_are_registered_with_the_feature() {
+    let output = sss().args(["--emit=ir", "tests/hashing.sss"]).assert().success().get_output().clone();
+    let ir = String::from_utf8_lossy(&output.stdout);
+
+    assert!(ir.contains("_t2 = base64_encode(_t1)\n"), "{}", ir);
+    assert!(ir.contains("_t11 = md5_file(_t10)\ndigest = _t11\n"), "{}", ir);
+}
+
+#[cfg(not(feature = "hashing"))]
+#[test]
+fn hashing_builtins_are_unknown_without_the_feature() {
+    let output = sss().args(["--diagnostics=json", "tests/hashing.sss"]).assert().failure().get_output().clone();
+
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\"code\":\"unknown-function\""));
+}
+
+#[test]
+fn check_commands_reports_missing_programs() {
+    let output = sss().args(["check", "--check-commands", "tests/commands.sss"]).assert().success().get_output().clone();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stderr.contains("Command sss-no-such-tool is not found on the PATH"));
+    assert!(!stderr.contains("Command ls "));
+
+    sss().args(["check", "tests/commands.sss"]).assert().success().stderr("");
+}
+
+#[test]
+fn docs_are_generated_from_doc_comments() {
+    sss().args(["--docs", "tests/docs.sss"]).assert().success().stdout(concat!(
+        "## greet(name:str, greeting:str = \"hello\")\n\nGreets someone by name.\n\nThe greeting goes to stdout.\n",
+        "\n## undocumented(n:num) -> num\n",
+        "\n## count(rest:str...) -> num\n\nCounts the arguments.\n"
+    ));
+}
+
+#[test]
+fn cached_scripts_round_trip() {
+    let dir = std::env::temp_dir().join(format!("sss-cache-{}", std::process::id()));
+    let script = dir.join("multientry.sss");
+    let cache = dir.join("multientry.sssb");
+
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::copy("tests/cases/multientry.sss", &script).unwrap();
+
+    let parsed = sss().arg("--emit=ir").arg(&script).assert().success().get_output().stdout.clone();
+
+    // the first run writes the cache, and the second reads it
+    sss().args(["--cache", "--emit=ir"]).arg(&script).assert().success().stdout(parsed.clone());
+    assert!(cache.exists());
+
+    sss().args(["--cache", "--emit=ir"]).arg(&script).assert().success().stdout(parsed.clone());
+    sss().args(["--cache", "--entry", "test_greet"]).arg(&script).assert().success();
+
+    // a cache in another version of the format is ignored and written again
+    std::fs::write(&cache, 99u32.to_le_bytes()).unwrap();
+
+    sss().args(["--cache", "--emit=ir"]).arg(&script).assert().success().stdout(parsed);
+    assert_ne!(std::fs::read(&cache).unwrap(), 99u32.to_le_bytes());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
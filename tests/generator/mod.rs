@@ -0,0 +1,383 @@
+//! Random, valid scripts, built as data and rendered to source
+//!
+//! A script reads only the globals of its prelude, its function params, and what it declares,
+//! so every script generated parses. Shared by the round-trip tests, and usable by a fuzzer.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use proptest::sample::select;
+
+use std::rc::Rc;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Type {
+    Num,
+    Str,
+    Char,
+    NumArray,
+    StrArray,
+    NumMap,
+    StrMap
+}
+
+impl Type {
+    pub fn name(self) -> &'static str {
+        match self {
+            Type::Num => "num",
+            Type::Str => "str",
+            Type::Char => "char",
+            Type::NumArray => "num[]",
+            Type::StrArray => "str[]",
+            Type::NumMap => "map[num]",
+            Type::StrMap => "map[str]"
+        }
+    }
+}
+
+const TYPES: [Type; 7] = [Type::Num, Type::Str, Type::Char, Type::NumArray, Type::StrArray, Type::NumMap, Type::StrMap];
+
+/// The globals every script starts with, so there are always variables of each type to read
+const PRELUDE: [(&str, Type, &str); 7] = [
+    ("n", Type::Num, "1"),
+    ("s", Type::Str, "\"s\""),
+    ("c", Type::Char, "'c'"),
+    ("ns", Type::NumArray, "[1, 2]"),
+    ("ss", Type::StrArray, "[\"a\"]"),
+    ("nm", Type::NumMap, "{\"a\": 1}"),
+    ("sm", Type::StrMap, "{\"a\": \"b\"}")
+];
+
+/// The built-ins calls are generated to: the name, the types of its params, and its return type
+const BUILTINS: [(&str, &[Type], Type); 8] = [
+    ("line_count", &[Type::Str], Type::Num),
+    ("str_count", &[Type::Str, Type::Str], Type::Num),
+    ("char_to_code", &[Type::Char], Type::Num),
+    ("sum_of_arr", &[Type::NumArray], Type::Num),
+    ("str_trim_newlines", &[Type::Str], Type::Str),
+    ("char_to_str", &[Type::Char], Type::Str),
+    ("csv_join", &[Type::StrArray], Type::Str),
+    ("keys", &[Type::NumMap], Type::StrArray)
+];
+
+/// The methods of str calls are generated to, with the same shape as BUILTINS
+const STR_METHODS: [(&str, &[Type], Type); 3] = [
+    ("line_count", &[], Type::Num),
+    ("count", &[Type::Str], Type::Num),
+    ("trim_newlines", &[], Type::Str)
+];
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Literal(String), // a number, string, heredoc, or char, as written
+    Var(String),
+    BinOp(Box<Expr>, &'static str, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+    Array(Vec<Expr>),
+    Map(Vec<(String, Expr)>),
+    Index(String, Box<Expr>),
+    Call(String, Vec<(Option<String>, Expr)>), // each argument is named, or positional when the name is None
+    Method(Box<Expr>, &'static str, Vec<Expr>),
+    Block(Vec<(Type, Expr)>, Box<Expr>) // the declarations local to the block, then its value
+}
+
+#[derive(Debug, Clone)]
+pub enum Stmt {
+    Declare(Type, Expr), // of the next of v0, v1, ...
+    Assign(&'static str, Expr),
+    IndexAssign(&'static str, Expr, Expr),
+    Call(Expr)
+}
+
+/// A function named by its position, f0, f1, ..., with params p0, p1, ...
+#[derive(Debug, Clone)]
+pub struct Fun {
+    pub params: Vec<(Type, Option<String>)>, // the type and the literal default of each param
+    pub ret: Option<Type>,
+    pub body: Vec<(Type, Expr)> // declarations of l0, l1, ...
+}
+
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub funs: Vec<Fun>,
+    pub stmts: Vec<Stmt>
+}
+
+/// What an expression can read and call
+#[derive(Debug, Clone)]
+struct Scope {
+    vars: Vec<(String, Type)>,
+    funs: Vec<(String, Fun)> // only those with a return type
+}
+
+impl Scope {
+    fn vars(&self, ty: Type) -> Vec<String> {
+        self.vars.iter().filter(|(_, t)| *t == ty).map(|(v, _)| v.clone()).collect()
+    }
+}
+
+fn string_literal() -> BoxedStrategy<String> {
+    let quoted = "[a-z /*\\\\$'{}\n\t#]{0,8}".prop_map(|s| format!("\"{}\"", s));
+
+    // a heredoc can have quotes in it, just not three in a row
+    let heredoc = "[a-z \"\n]{0,12}".prop_filter("no \"\"\" in a heredoc", |s| !s.contains("\"\"\"")).prop_map(|s| format!("\"\"\"{}\"\"\"", s));
+
+    prop_oneof![4 => quoted, 1 => heredoc].boxed()
+}
+
+fn literal(ty: Type) -> BoxedStrategy<Expr> {
+    let num = prop_oneof![
+        (0u64..100_000).prop_map(|n| n.to_string()),
+        (0u32..1000, 0u32..1000).prop_map(|(a, b)| format!("{}.{}", a, b)),
+        (0u32..100).prop_map(|a| format!("{}.", a))
+    ];
+
+    match ty {
+        Type::Num => num.prop_map(Expr::Literal).boxed(),
+        Type::Str => string_literal().prop_map(Expr::Literal).boxed(),
+        Type::Char => select(vec!["'a'", "' '", "'\\n'", "'\\t'", "'\\\\'", "'\\''", "'\"'"]).prop_map(|c| Expr::Literal(String::from(c))).boxed(),
+        Type::NumArray => vec(num, 1..4).prop_map(|ns| Expr::Array(ns.into_iter().map(Expr::Literal).collect())).boxed(),
+        Type::StrArray => vec(string_literal(), 1..4).prop_map(|ss| Expr::Array(ss.into_iter().map(Expr::Literal).collect())).boxed(),
+        Type::NumMap => num.prop_map(|n| Expr::Map(vec![(String::from("k"), Expr::Literal(n))])).boxed(),
+        Type::StrMap => string_literal().prop_map(|s| Expr::Map(vec![(String::from("k"), Expr::Literal(s))])).boxed()
+    }
+}
+
+/// Calls to built-ins and user functions that return ty, with arguments from expr
+fn calls(ty: Type, scope: &Rc<Scope>, depth: u32) -> Vec<BoxedStrategy<Expr>> {
+    let mut calls = Vec::new();
+
+    for (name, params, _) in BUILTINS.iter().filter(|(_, _, ret)| *ret == ty) {
+        let args = params.iter().map(|p| expr(*p, scope.clone(), depth).prop_map(|e| (None, e)).boxed()).collect::<Vec<_>>();
+
+        calls.push(args.prop_map(move |args| Expr::Call(String::from(*name), args)).boxed());
+    }
+
+    let receivers = scope.vars(Type::Str);
+
+    for (name, params, _) in STR_METHODS.iter().filter(|(_, _, ret)| *ret == ty && !receivers.is_empty()) {
+        let args = params.iter().map(|p| expr(*p, scope.clone(), depth)).collect::<Vec<_>>();
+
+        calls.push((select(receivers.clone()), args).prop_map(move |(receiver, args)| Expr::Method(Box::new(Expr::Var(receiver)), name, args)).boxed());
+    }
+
+    for (name, fun) in scope.funs.iter().filter(|(_, f)| f.ret == Some(ty)) {
+        calls.push(call_args(fun, scope, depth).prop_map({
+            let name = name.clone();
+            move |args| Expr::Call(name.clone(), args)
+        }).boxed());
+    }
+
+    calls
+}
+
+/// The arguments of a call to fun: all positional, leaving out some defaults, or all named, in any order
+fn call_args(fun: &Fun, scope: &Rc<Scope>, depth: u32) -> BoxedStrategy<Vec<(Option<String>, Expr)>> {
+    let required = fun.params.iter().take_while(|(_, default)| default.is_none()).count();
+    let args = fun.params.iter().map(|(t, _)| expr(*t, scope.clone(), depth)).collect::<Vec<_>>();
+    let count = fun.params.len();
+
+    (args, required..=count, any::<bool>()).prop_flat_map(move |(args, len, named)| {
+        let args = args.into_iter().enumerate().take(len).map(|(i, e)| (if named { Some(format!("p{}", i)) } else { None }, e)).collect::<Vec<_>>();
+
+        if named { Just(args).prop_shuffle().boxed() } else { Just(args).boxed() }
+    }).boxed()
+}
+
+/// An expression of type ty, nested at most depth deep
+fn expr(ty: Type, scope: Rc<Scope>, depth: u32) -> BoxedStrategy<Expr> {
+    let vars = scope.vars(ty);
+    let leaf = if vars.is_empty() { literal(ty) } else { prop_oneof![literal(ty), select(vars).prop_map(Expr::Var)].boxed() };
+
+    if depth == 0 {
+        return leaf;
+    }
+
+    let depth = depth - 1;
+    let mut options = vec![leaf.clone(), leaf];
+    let sub = |t| expr(t, scope.clone(), depth);
+    let index = |map: Type| {
+        let maps = scope.vars(map);
+
+        (!maps.is_empty()).then(|| (select(maps), sub(Type::Str)).prop_map(|(m, key)| Expr::Index(m, Box::new(key))).boxed())
+    };
+
+    match ty {
+        Type::Num => {
+            let ops = select(vec!["+", "-", "*", "/"]);
+
+            options.push((sub(Type::Num), ops, sub(Type::Num)).prop_map(|(l, op, r)| Expr::BinOp(Box::new(l), op, Box::new(r))).boxed());
+            options.extend(index(Type::NumMap));
+        },
+        Type::Str => {
+            options.push((sub(Type::Str), sub(Type::Str)).prop_map(|(l, r)| Expr::BinOp(Box::new(l), "+", Box::new(r))).boxed());
+            options.extend(index(Type::StrMap));
+        },
+        Type::NumArray | Type::StrArray => {
+            let element = if ty == Type::NumArray { Type::Num } else { Type::Str };
+
+            options.push(vec(sub(element), 1..4).prop_map(Expr::Array).boxed());
+        },
+        Type::NumMap | Type::StrMap => {
+            let value = if ty == Type::NumMap { Type::Num } else { Type::Str };
+
+            options.push(vec(sub(value), 1..3).prop_map(|values| {
+                Expr::Map(values.into_iter().enumerate().map(|(i, v)| (format!("k{}", i), v)).collect())
+            }).boxed());
+        },
+        Type::Char => { }
+    }
+
+    if matches!(ty, Type::Num | Type::Str | Type::Char) {
+        options.push((sub(Type::Num), sub(ty), sub(ty)).prop_map(|(c, t, e)| Expr::Conditional(Box::new(c), Box::new(t), Box::new(e))).boxed());
+    }
+
+    options.extend(calls(ty, &scope, depth));
+
+    let block_scope = scope.clone();
+    let locals = vec(select(TYPES.to_vec()).prop_flat_map(move |t| (Just(t), expr(t, block_scope.clone(), depth))), 0..3);
+
+    options.push((locals, sub(ty)).prop_map(|(locals, value)| Expr::Block(locals, Box::new(value))).boxed());
+
+    proptest::strategy::Union::new(options).boxed()
+}
+
+fn fun() -> BoxedStrategy<Fun> {
+    let param = (select(TYPES.to_vec()), any::<bool>());
+
+    (vec(param, 0..4), proptest::option::of(select(vec![Type::Num, Type::Str]))).prop_flat_map(|(params, ret)| {
+        // defaults are only on trailing params, and only literals of num or str are generated
+        let can_default = |(t, d): &(Type, bool)| *d && matches!(t, Type::Num | Type::Str);
+        let first_default = params.len() - params.iter().rev().take_while(|p| can_default(p)).count();
+        let defaults = params.iter().enumerate().map(|(i, (t, _))| {
+            if i >= first_default { literal(*t).prop_map(|e| Some(render(&e))).boxed() } else { Just(None).boxed() }
+        }).collect::<Vec<_>>();
+
+        let types = params.iter().map(|(t, _)| *t).collect::<Vec<_>>();
+        let scope = Rc::new(Scope { vars: types.iter().enumerate().map(|(i, t)| (format!("p{}", i), *t)).collect(), funs: Vec::new() });
+        let body = vec(select(TYPES.to_vec()).prop_flat_map(move |t| (Just(t), expr(t, scope.clone(), 2))), 1..4);
+
+        (defaults, body).prop_map(move |(defaults, body)| {
+            Fun { params: types.iter().copied().zip(defaults).collect(), ret, body }
+        })
+    }).boxed()
+}
+
+fn stmt(scope: Rc<Scope>) -> BoxedStrategy<Stmt> {
+    let globals = PRELUDE.iter().map(|(name, t, _)| (*name, *t)).collect::<Vec<_>>();
+    let declare = select(TYPES.to_vec()).prop_flat_map({
+        let scope = scope.clone();
+        move |t| expr(t, scope.clone(), 3).prop_map(move |e| Stmt::Declare(t, e))
+    });
+    let assign = select(globals).prop_flat_map({
+        let scope = scope.clone();
+        move |(name, t)| expr(t, scope.clone(), 2).prop_map(move |e| Stmt::Assign(name, e))
+    });
+    let index_assign = (expr(Type::Str, scope.clone(), 1), expr(Type::Num, scope.clone(), 2)).prop_map(|(k, v)| Stmt::IndexAssign("nm", k, v));
+
+
+    let mut options = vec![declare.boxed(), assign.boxed(), index_assign.boxed()];
+
+    // a call whose result is not used, which is a warning rather than an error
+    let call_types = scope.funs.iter().filter_map(|(_, f)| f.ret).collect::<Vec<_>>();
+
+    if !call_types.is_empty() {
+        options.push(select(call_types).prop_flat_map(move |t| {
+            proptest::strategy::Union::new(calls(t, &scope, 1)).prop_map(Stmt::Call)
+        }).boxed());
+    }
+
+    proptest::strategy::Union::new(options).boxed()
+}
+
+/// A script with a few functions, and the statements of its top-level code
+pub fn script() -> impl Strategy<Value = Script> {
+    vec(fun(), 0..3).prop_flat_map(|funs| {
+        let vars = PRELUDE.iter().map(|(name, t, _)| (String::from(*name), *t)).collect();
+        let callable = funs.iter().enumerate().filter(|(_, f)| f.ret.is_some()).map(|(i, f)| (format!("f{}", i), f.clone())).collect();
+        let scope = Rc::new(Scope { vars, funs: callable });
+
+        (Just(funs), vec(stmt(scope), 1..6)).prop_map(|(funs, stmts)| Script { funs, stmts })
+    })
+}
+
+/// Renders an expression as source
+pub fn render(e: &Expr) -> String {
+    render_in(e, 0)
+}
+
+/// Renders an expression inside block blocks. The locals of each block are named for how deeply it is
+/// nested, b0_0, b0_1, ..., so those of a nested block don't clash, while those of sibling blocks do.
+fn render_in(e: &Expr, block: usize) -> String {
+    let r = |e: &Expr| render_in(e, block);
+
+    // where only a primary can go, like an operand
+    let primary = |e: &Expr| match e {
+        Expr::BinOp(..) | Expr::Conditional(..) => format!("({})", r(e)),
+        _ => r(e)
+    };
+
+    match e {
+        Expr::Literal(s) | Expr::Var(s) => s.clone(),
+        // operators go left to right, so only the right operand needs parens around a chain
+        Expr::BinOp(l, op, rhs) => {
+            let left = if let Expr::BinOp(..) = **l { r(l) } else { primary(l) };
+
+            format!("{} {} {}", left, op, primary(rhs))
+        },
+        Expr::Conditional(c, t, f) => format!("{} ? {} : {}", primary(c), primary(t), primary(f)),
+        Expr::Array(items) => format!("[{}]", items.iter().map(r).collect::<Vec<_>>().join(", ")),
+        Expr::Map(entries) => format!("{{{}}}", entries.iter().map(|(k, v)| format!("\"{}\": {}", k, r(v))).collect::<Vec<_>>().join(", ")),
+        Expr::Index(map, key) => format!("{}[{}]", map, r(key)),
+        Expr::Call(name, args) => {
+            let args = args.iter().map(|(n, a)| n.as_ref().map_or_else(|| r(a), |n| format!("{} = {}", n, r(a))));
+
+            format!("{}({})", name, args.collect::<Vec<_>>().join(", "))
+        },
+        Expr::Method(receiver, name, args) => format!("{}.{}({})", r(receiver), name, args.iter().map(r).collect::<Vec<_>>().join(", ")),
+        Expr::Block(locals, value) => {
+            let locals = locals.iter().enumerate().map(|(i, (t, e))| format!("var b{}_{}:{} = {}; ", block, i, t.name(), render_in(e, block + 1)));
+
+            format!("{{ {}{} }}", locals.collect::<String>(), render_in(value, block + 1))
+        }
+    }
+}
+
+impl Script {
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+
+        for (name, t, value) in PRELUDE.iter() {
+            source.push_str(&format!("var {}:{} = {};\n", name, t.name(), value));
+        }
+
+        for (i, fun) in self.funs.iter().enumerate() {
+            let params = fun.params.iter().enumerate().map(|(p, (t, default))| match default {
+                Some(d) => format!("p{}:{} = {}", p, t.name(), d),
+                None => format!("p{}:{}", p, t.name())
+            });
+            let ret = fun.ret.map_or_else(String::new, |t| format!(" -> {}", t.name()));
+
+            source.push_str(&format!("\nfun f{}({}){} {{\n", i, params.collect::<Vec<_>>().join(", "), ret));
+
+            for (l, (t, e)) in fun.body.iter().enumerate() {
+                source.push_str(&format!("    var l{}:{} = {};\n", l, t.name(), render(e)));
+            }
+
+            source.push_str("}\n\n");
+        }
+
+        for (v, stmt) in self.stmts.iter().enumerate() {
+            let line = match stmt {
+                Stmt::Declare(t, e) => format!("var v{}:{} = {};", v, t.name(), render(e)),
+                Stmt::Assign(name, e) => format!("{} = {};", name, render(e)),
+                Stmt::IndexAssign(map, k, e) => format!("{}[{}] = {};", map, render(k), render(e)),
+                Stmt::Call(e) => format!("{};", render(e))
+            };
+
+            source.push_str(&line);
+            source.push('\n');
+        }
+
+        source
+    }
+}
@@ -0,0 +1,95 @@
+//! Golden-file tests over the scripts in tests/cases and tests/highlight
+//!
+//! - `name.sss` must parse, and its `--emit=ir` dump must match `name.expected`
+//! - `name.err.sss` must fail to parse, and the error (line, source, and reason) must match `name.err.expected`
+//! - in tests/highlight, the `--emit=highlight` spans of `name.sss` must match `name.expected`
+//! - the `--emit=source` of each `name.sss` must parse to the same code as `name.sss`
+//!
+//! Run with `UPDATE_EXPECT=1` to regenerate the expected files instead of comparing against them.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn cases(dir: &str) -> Vec<PathBuf> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests").join(dir);
+
+    let mut cases = fs::read_dir(&dir).unwrap_or_else(|e| panic!("Error reading {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| matches!(path.extension(), Some(ext) if ext == "sss"))
+        .collect::<Vec<_>>();
+
+    cases.sort();
+
+    cases
+}
+
+/// Runs sss with the given flag over every case in the directory, comparing the output to the expected files
+fn check_cases(dir: &str, flag: &str) {
+    let update = env::var_os("UPDATE_EXPECT").is_some();
+    let mut failures = Vec::new();
+
+    for case in cases(dir) {
+        let expect_error = case.to_string_lossy().ends_with(".err.sss");
+        let expected_path = case.with_extension("expected");
+
+        let output = Command::new(env!("CARGO_BIN_EXE_sss")).arg(flag).arg(&case).output().expect("Error running sss");
+        let actual = String::from_utf8_lossy(if expect_error { &output.stderr } else { &output.stdout }).into_owned();
+
+        if output.status.success() == expect_error {
+            failures.push(format!("{}: expected {}, got:\n{}{}", case.display(),
+                                  if expect_error { "a parse error" } else { "a successful parse" },
+                                  String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr)));
+            continue;
+        }
+
+        if update {
+            fs::write(&expected_path, &actual).expect("Error writing expected file");
+            continue;
+        }
+
+        let expected = fs::read_to_string(&expected_path).unwrap_or_default();
+
+        if expected != actual {
+            failures.push(format!("{}: output does not match {}\n--- expected\n{}--- actual\n{}", case.display(), expected_path.display(), expected, actual));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} golden case(s) failed:\n\n{}", failures.len(), failures.join("\n"));
+}
+
+#[test]
+fn golden_cases() {
+    check_cases("cases", "--emit=ir");
+}
+
+#[test]
+fn highlight_cases() {
+    check_cases("highlight", "--emit=highlight");
+}
+
+fn emit(flag: &str, path: &Path) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_sss")).arg(flag).arg(path).output().expect("Error running sss");
+
+    assert!(output.status.success(), "{} {} failed:\n{}", flag, path.display(), String::from_utf8_lossy(&output.stderr));
+
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn source_round_trips() {
+    let dir = env::temp_dir().join(format!("sss-round-trip-{}", std::process::id()));
+
+    fs::create_dir_all(&dir).unwrap();
+
+    for case in cases("cases").into_iter().filter(|c| !c.to_string_lossy().ends_with(".err.sss")) {
+        let regenerated = dir.join(case.file_name().unwrap());
+
+        fs::write(&regenerated, emit("--emit=source", &case)).unwrap();
+
+        assert_eq!(emit("--emit=ir", &case), emit("--emit=ir", &regenerated), "{} does not round-trip", case.display());
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,72 @@
+//! Property test of --emit=source: random scripts from tests/generator must lower to the same code
+//! after being written back as source and parsed again, up to the numbering of the temps
+
+mod generator;
+
+use proptest::prelude::*;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Runs sss with flag on the script at path, returning its stdout, or its stderr when it fails
+fn emit(flag: &str, path: &Path) -> Result<String, String> {
+    let output = Command::new(env!("CARGO_BIN_EXE_sss")).arg(flag).arg(path).output().expect("Error running sss");
+
+    if output.status.success() {
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    } else {
+        Err(String::from_utf8_lossy(&output.stderr).into_owned())
+    }
+}
+
+/// Renumbers the temps in the order they first appear, so code that lowers the same way compares equal
+fn renumber_temps(ir: &str) -> String {
+    let mut temps = Vec::<String>::new();
+    let mut ret = String::with_capacity(ir.len());
+    let mut rest = ir;
+
+    while let Some(i) = rest.find("_t") {
+        let digits = rest[i + 2..].chars().take_while(|c| c.is_ascii_digit()).count();
+        let is_temp = digits > 0 && !rest[..i].ends_with(|c: char| c.is_ascii_alphanumeric() || c == '_');
+
+        ret.push_str(&rest[..i]);
+
+        if is_temp {
+            let name = &rest[i..i + 2 + digits];
+            let n = temps.iter().position(|t| t == name).unwrap_or_else(|| { temps.push(String::from(name)); temps.len() - 1 });
+
+            ret.push_str(&format!("_t{}", n + 1));
+        } else {
+            ret.push_str(&rest[i..i + 2 + digits]);
+        }
+
+        rest = &rest[i + 2 + digits..];
+    }
+
+    ret.push_str(rest);
+    ret
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig { cases: 1000, failure_persistence: None, ..ProptestConfig::default() })]
+
+    #[test]
+    fn generated_scripts_round_trip(script in generator::script()) {
+        let dir = env::temp_dir().join(format!("sss-generated-{}", std::process::id()));
+        let (original, regenerated) = (dir.join("original.sss"), dir.join("regenerated.sss"));
+
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&original, script.to_source()).unwrap();
+
+        let ir = emit("--emit=ir", &original).unwrap_or_else(|e| panic!("The generated script does not parse:\n{}\n{}", script.to_source(), e));
+        let source = emit("--emit=source", &original).unwrap();
+
+        fs::write(&regenerated, &source).unwrap();
+
+        let regenerated_ir = emit("--emit=ir", &regenerated).unwrap_or_else(|e| panic!("{}\ndoes not parse:\n{}", source, e));
+
+        prop_assert_eq!(renumber_temps(&ir), renumber_temps(&regenerated_ir), "\n{}\nwas written back as\n{}", script.to_source(), source);
+    }
+}